@@ -0,0 +1,95 @@
+use clap::{Parser, Subcommand};
+use ssb_ref::{FeedRef, MsgRef, RefError};
+use std::{net::SocketAddr, path::PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about = "Archive and query a Secure Scuttlebutt log")]
+pub struct Cli {
+    /// Path to a TOML config file
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Path to the flume offset log [default: ~/.ssb/flume/log.offset]
+    #[arg(long, global = true)]
+    pub log_path: Option<PathBuf>,
+
+    /// Path to the sqlite database [default: ./db.sqlite3]
+    #[arg(long, global = true)]
+    pub sql_path: Option<PathBuf>,
+
+    /// Path to a secret file to decrypt private messages with, can be repeated [default: ~/.ssb/secret]
+    #[arg(long = "secret", global = true)]
+    pub secret_paths: Vec<PathBuf>,
+
+    /// Seal the decrypted content of private messages in the database with a key derived from the first secret
+    #[arg(long, global = true)]
+    pub seal_decrypted_contents: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Index the offset log into the sqlite database
+    Index {
+        /// Number of log entries to process per step
+        #[arg(long, default_value_t = 20_000)]
+        chunk_size: u64,
+        /// Keep running and index new log entries as they are appended
+        #[arg(long)]
+        watch: bool,
+        /// How often to check the log for new entries when watching, in milliseconds
+        #[arg(long, default_value_t = 1_000)]
+        poll_interval: u64,
+    },
+    /// Print messages as json
+    Query {
+        /// Message to get
+        #[arg(long, value_parser = parse_msg_ref, conflicts_with = "feed")]
+        msg: Option<MsgRef>,
+        /// Feed to list messages from
+        #[arg(long, value_parser = parse_feed_ref, required_unless_present = "msg")]
+        feed: Option<FeedRef>,
+        /// Only list messages with this content type
+        #[arg(long = "type")]
+        content_type: Option<String>,
+        /// Only list private messages that the secret keys decrypted
+        #[arg(long)]
+        private: bool,
+        /// Maximum number of messages to list
+        #[arg(long, default_value_t = 10)]
+        limit: i64,
+    },
+    /// Export all messages of a feed as newline-delimited json, newest first
+    Export {
+        /// Feed to export
+        #[arg(long, value_parser = parse_feed_ref)]
+        feed: FeedRef,
+        /// File to write to [default: stdout]
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Print statistics about the database
+    Stats,
+    /// Serve html pages over http, while indexing new log entries
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Number of log entries to process per step
+        #[arg(long, default_value_t = 20_000)]
+        chunk_size: u64,
+        /// How often to check the log for new entries, in milliseconds
+        #[arg(long, default_value_t = 1_000)]
+        poll_interval: u64,
+    },
+}
+
+fn parse_feed_ref(value: &str) -> Result<FeedRef, RefError> {
+    FeedRef::from_string(value.to_owned())
+}
+
+fn parse_msg_ref(value: &str) -> Result<MsgRef, RefError> {
+    MsgRef::from_string(value.to_owned())
+}
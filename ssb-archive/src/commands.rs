@@ -0,0 +1,132 @@
+use futures::{pin_mut, StreamExt};
+use ssb_db::{Database, DatabaseReader, SelectAllMsgsByFeedOptions};
+use ssb_ref::{FeedRef, MsgRef};
+use std::{
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::Error;
+
+const EXPORT_PAGE_SIZE: i64 = 1_000;
+
+pub async fn index(db: &mut Database, chunk_size: u64) -> Result<(), Error> {
+    let log_latest = db.get_log_latest().await.unwrap_or(0);
+    progress_bar::init_progress_bar(log_latest as usize);
+    let mut sql_latest = db.get_sql_latest().await?;
+    loop {
+        if let Some(sql_latest) = sql_latest {
+            progress_bar::set_progress_bar_progression(sql_latest as usize);
+        }
+        db.process(chunk_size).await?;
+
+        // the log is empty or fully indexed once a chunk indexes nothing
+        let processed_latest = db.get_sql_latest().await?;
+        if processed_latest == sql_latest {
+            break;
+        }
+        sql_latest = processed_latest;
+    }
+    progress_bar::finalize_progress_bar();
+
+    Ok(())
+}
+
+pub async fn watch(
+    db: &mut Database,
+    chunk_size: u64,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    let msgs = db.tail(chunk_size, poll_interval);
+    pin_mut!(msgs);
+    while let Some(msg) = msgs.next().await {
+        let msg = msg?;
+        println!("{}", msg.key.to_string());
+    }
+
+    Ok(())
+}
+
+pub async fn query(
+    db: &DatabaseReader,
+    msg_ref: Option<MsgRef>,
+    feed_ref: Option<FeedRef>,
+    content_type: Option<String>,
+    private: bool,
+    limit: i64,
+) -> Result<(), Error> {
+    let msgs = if let Some(msg_ref) = msg_ref {
+        db.get_msg(msg_ref).await?.into_iter().collect()
+    } else if let Some(feed_ref) = feed_ref {
+        db.get_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+            feed_ref: &feed_ref,
+            content_type: content_type.as_deref(),
+            page_size: limit,
+            less_than_feed_seq: i64::MAX,
+            is_decrypted: private,
+        })
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    let mut out = stdout().lock();
+    for msg in msgs {
+        serde_json::to_writer_pretty(&mut out, &msg)?;
+        writeln!(out).map_err(Error::Write)?;
+    }
+
+    Ok(())
+}
+
+pub async fn export(
+    db: &DatabaseReader,
+    feed_ref: FeedRef,
+    output: Option<PathBuf>,
+) -> Result<(), Error> {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(&path).map_err(|err| Error::CreateFile(path, err))?,
+        )),
+        None => Box::new(stdout().lock()),
+    };
+
+    let msgs = db.stream_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+        feed_ref: &feed_ref,
+        content_type: None,
+        page_size: EXPORT_PAGE_SIZE,
+        less_than_feed_seq: i64::MAX,
+        is_decrypted: false,
+    });
+    pin_mut!(msgs);
+    while let Some(msg) = msgs.next().await {
+        serde_json::to_writer(&mut out, &msg?)?;
+        writeln!(out).map_err(Error::Write)?;
+    }
+    out.flush().map_err(Error::Write)?;
+
+    Ok(())
+}
+
+pub async fn stats(db: &mut Database) -> Result<(), Error> {
+    let log_latest = db.get_log_latest().await;
+    let sql_latest = db.get_sql_latest().await?;
+    let stats = db.reader().get_stats().await?;
+
+    println!("log latest offset: {}", display_option(log_latest));
+    println!("indexed latest offset: {}", display_option(sql_latest));
+    println!("messages: {}", stats.msgs_count);
+    println!("feeds: {}", stats.feeds_count);
+    println!("content types:");
+    for (content_type, count) in stats.content_type_counts {
+        println!("  {}: {}", content_type, count);
+    }
+
+    Ok(())
+}
+
+fn display_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_string(), |v| v.to_string())
+}
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use simple_home_dir::home_dir as get_home_dir;
+use std::{env::current_dir, fs::read_to_string, path::PathBuf};
+
+use crate::{cli::Cli, Error};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    pub log_path: Option<PathBuf>,
+    pub sql_path: Option<PathBuf>,
+    pub secret_paths: Option<Vec<PathBuf>>,
+    pub seal_decrypted_contents: bool,
+}
+
+impl ConfigFile {
+    pub fn load(path: &PathBuf) -> Result<Self, Error> {
+        let string = read_to_string(path).map_err(|err| Error::ReadConfig(path.clone(), err))?;
+        toml::from_str(&string).map_err(|err| Error::ParseConfig(path.clone(), err))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub log_path: PathBuf,
+    pub sql_path: PathBuf,
+    pub secret_paths: Vec<PathBuf>,
+    pub seal_decrypted_contents: bool,
+}
+
+impl Config {
+    // Flags take priority over the config file, which takes priority over the defaults
+    pub fn from_cli(cli: &Cli) -> Result<Self, Error> {
+        let file = match &cli.config {
+            Some(path) => ConfigFile::load(path)?,
+            None => ConfigFile::default(),
+        };
+
+        let home_dir = get_home_dir().ok_or(Error::HomeDir)?;
+        let cwd = current_dir().map_err(Error::CurrentDir)?;
+
+        let log_path = cli
+            .log_path
+            .clone()
+            .or(file.log_path)
+            .unwrap_or_else(|| home_dir.join(".ssb/flume/log.offset"));
+        let sql_path = cli
+            .sql_path
+            .clone()
+            .or(file.sql_path)
+            .unwrap_or_else(|| cwd.join("db.sqlite3"));
+        // the default secret is optional, so it is only used when it exists
+        let secret_paths = match (cli.secret_paths.is_empty(), file.secret_paths) {
+            (false, _) => cli.secret_paths.clone(),
+            (true, Some(secret_paths)) => secret_paths,
+            (true, None) => Some(home_dir.join(".ssb/secret"))
+                .filter(|path| path.exists())
+                .into_iter()
+                .collect(),
+        };
+        Ok(Self {
+            log_path,
+            sql_path,
+            secret_paths,
+            seal_decrypted_contents: cli.seal_decrypted_contents || file.seal_decrypted_contents,
+        })
+    }
+}
@@ -1,13 +1,17 @@
-// use std::{thread::sleep, time::Duration};
+use clap::Parser;
+use ssb_db::{
+    content_key_from_secret, load_secret_file, Database, Error as DatabaseError, SecretError,
+};
+use std::{io, path::PathBuf, time::Duration};
+use thiserror::Error as ThisError;
 
-use std::{env::current_dir, io, path::Path};
+mod cli;
+mod commands;
+mod config;
+mod serve;
 
-use progress_bar;
-use simple_home_dir::home_dir as get_home_dir;
-use ssb_db::{Database, Error as DatabaseError, SelectAllMsgsByFeedOptions};
-use ssb_markdown::render;
-use ssb_ref::{FeedRef, RefError};
-use thiserror::Error as ThisError;
+use cli::{Cli, Command};
+use config::Config;
 
 #[tokio::main]
 async fn main() {
@@ -20,162 +24,80 @@ async fn main() {
 }
 
 #[derive(Debug, ThisError)]
-enum Error {
+pub enum Error {
     #[error("Failed to get home dir")]
     HomeDir,
     #[error("Failed to get current dir: {0}")]
     CurrentDir(#[source] io::Error),
+    #[error("Failed to read config file {0}: {1}")]
+    ReadConfig(PathBuf, #[source] io::Error),
+    #[error("Failed to parse config file {0}: {1}")]
+    ParseConfig(PathBuf, #[source] toml::de::Error),
+    #[error("Failed to create file {0}: {1}")]
+    CreateFile(PathBuf, #[source] io::Error),
+    #[error("Failed to write output: {0}")]
+    Write(#[source] io::Error),
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Server error: {0}")]
+    Serve(#[source] hyper::Error),
     #[error("Database error: {0}")]
     Database(#[from] DatabaseError),
-    #[error("Ref format error: {0}")]
-    RefFormat(#[from] RefError),
+    #[error("Failed to load secret: {0}")]
+    Secret(#[from] SecretError),
+    #[error("Sealing decrypted contents needs a secret to derive the key from")]
+    NoSecretToSealWith,
 }
 
 async fn exec() -> Result<(), Error> {
-    let cwd = current_dir().map_err(Error::CurrentDir)?;
-    let home_dir = get_home_dir().ok_or(Error::HomeDir)?;
-    let log_path = home_dir.join(Path::new(".ssb/flume/log.offset"));
-    let sql_path = cwd.join(Path::new("db.sqlite3"));
+    let cli = Cli::parse();
+    let config = Config::from_cli(&cli)?;
 
-    let mut db = Database::new(log_path, sql_path, Vec::new()).await?;
+    let keys = config
+        .secret_paths
+        .iter()
+        .map(load_secret_file)
+        .collect::<Result<Vec<_>, _>>()?;
+    let content_key = match (config.seal_decrypted_contents, keys.first()) {
+        (true, Some(key)) => Some(content_key_from_secret(key)),
+        (true, None) => return Err(Error::NoSecretToSealWith),
+        (false, _) => None,
+    };
+    let mut db = Database::new(
+        &config.log_path,
+        &config.sql_path,
+        keys,
+        content_key,
+        Vec::new(),
+    )
+    .await?;
 
-    let log_latest = db.get_log_latest().await.unwrap_or(0);
-    progress_bar::init_progress_bar(log_latest as usize);
-    loop {
-        if let Some(sql_latest) = db.get_sql_latest().await? {
-            progress_bar::set_progress_bar_progression(sql_latest as usize);
-            if log_latest == sql_latest {
-                progress_bar::finalize_progress_bar();
-                break;
+    match cli.command {
+        Command::Index {
+            chunk_size,
+            watch,
+            poll_interval,
+        } => {
+            commands::index(&mut db, chunk_size).await?;
+            if watch {
+                commands::watch(&mut db, chunk_size, Duration::from_millis(poll_interval)).await?;
             }
         }
-        db.process(20_000).await?;
-        // sleep(Duration::from_secs(1))
-    }
-
-    let feed_ref: FeedRef = "@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519"
-        .to_owned()
-        .try_into()?;
-    let max_feed_seq = db.get_max_seq_by_feed(&feed_ref).await.unwrap();
-
-    let messages = db
-        .get_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
-            feed_ref: &feed_ref,
-            content_type: "post",
-            page_size: 10,
-            less_than_feed_seq: max_feed_seq + 1,
-            is_decrypted: false,
-        })
-        .await?;
-
-    for message in messages {
-        println!("{:?}", message.value.content["text"]);
-        let content = message.value.content;
-        let text = content["text"].as_str().unwrap();
-        let html = render(text);
-        println!("{:?}", html);
+        Command::Query {
+            msg,
+            feed,
+            content_type,
+            private,
+            limit,
+        } => commands::query(&db.reader(), msg, feed, content_type, private, limit).await?,
+        Command::Export { feed, output } => commands::export(&db.reader(), feed, output).await?,
+        Command::Stats => commands::stats(&mut db).await?,
+        Command::Serve {
+            addr,
+            chunk_size,
+            poll_interval,
+        } => serve::serve(db, addr, chunk_size, Duration::from_millis(poll_interval)).await?,
     }
 
-    // select all posts by a user
-    //   - greater than seq
-    //   - limit 10
-    /*
-    SELECT
-      seq,
-      keys.key as key,
-      authors.author as author,
-      asserted_time,
-      content_type,
-      content,
-      is_decrypted,
-      root_keys.key as root,
-      fork_keys.key as fork
-    FROM messages_raw
-    JOIN keys ON keys.id=messages_raw.key_id
-    LEFT JOIN keys AS root_keys ON root_keys.id=messages_raw.root_id
-    LEFT JOIN keys AS fork_keys ON fork_keys.id=messages_raw.fork_id
-    JOIN authors ON authors.id=messages_raw.author_id
-    WHERE
-            authors.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-            AND content_type = 'post'
-            AND seq > 10
-    LIMIT 10
-    */
-
-    // select all reply posts in a thread
-    /*
-    SELECT
-      keys.key as key,
-      authors.author as author,
-      asserted_time,
-      content_type,
-      content,
-      is_decrypted,
-      root_keys.key as root,
-      fork_keys.key as fork
-    FROM messages_raw
-    JOIN keys ON keys.id=messages_raw.key_id
-    LEFT JOIN keys AS root_keys ON root_keys.id=messages_raw.root_id
-    LEFT JOIN keys AS fork_keys ON fork_keys.id=messages_raw.fork_id
-    JOIN authors ON authors.id=messages_raw.author_id
-    WHERE
-      root = '%R/m9I+QW+AwEq7sObyEuAc1kCDGbk1neK0STJKSnpyY=.sha256'
-    */
-
-    // get self-described about
-    /*
-    SELECT
-    (
-        SELECT
-            JSON_EXTRACT(messages_raw.content, "$.name") as name
-        FROM abouts_raw
-        JOIN keys AS keys_from ON keys_from.id = abouts_raw.link_from_key_id
-        JOIN messages_raw ON link_from_key_id = messages_raw.key_id
-        JOIN authors AS authors_from ON authors_from.id = messages_raw.author_id
-        LEFT JOIN authors AS authors_to ON authors_to.id=abouts_raw.link_to_author_id
-        WHERE
-          JSON_EXTRACT(messages_raw.content, '$.name') IS NOT NULL
-          AND authors_from.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-          AND authors_to.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-        ORDER BY abouts_raw.id
-        DESC
-        LIMIT 1
-    ) as name,
-    (
-        SELECT
-            JSON_EXTRACT(messages_raw.content, "$.image") as image
-        FROM abouts_raw
-        JOIN keys AS keys_from ON keys_from.id = abouts_raw.link_from_key_id
-        JOIN messages_raw ON link_from_key_id = messages_raw.key_id
-        JOIN authors AS authors_from ON authors_from.id = messages_raw.author_id
-        LEFT JOIN authors AS authors_to ON authors_to.id=abouts_raw.link_to_author_id
-        WHERE
-          JSON_EXTRACT(messages_raw.content, '$.image') IS NOT NULL
-          AND authors_from.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-          AND authors_to.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-        ORDER BY abouts_raw.id
-        DESC
-        LIMIT 1
-    ) as image,
-    (
-        SELECT
-            JSON_EXTRACT(messages_raw.content, "$.description") as description
-        FROM abouts_raw
-        JOIN keys AS keys_from ON keys_from.id = abouts_raw.link_from_key_id
-        JOIN messages_raw ON link_from_key_id = messages_raw.key_id
-        JOIN authors AS authors_from ON authors_from.id = messages_raw.author_id
-        LEFT JOIN authors AS authors_to ON authors_to.id=abouts_raw.link_to_author_id
-        WHERE
-          JSON_EXTRACT(messages_raw.content, '$.description') IS NOT NULL
-          AND authors_from.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-          AND authors_to.author = '@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519'
-        ORDER BY abouts_raw.id
-        DESC
-        LIMIT 1
-    ) as description
-    */
-
-    println!("Done!");
-
     Ok(())
 }
@@ -0,0 +1,103 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router, Server,
+};
+use futures::{pin_mut, StreamExt};
+use ssb_db::{Database, DatabaseReader, PostView, SelectAllMsgsByFeedOptions};
+use ssb_pages::render_post;
+use ssb_ref::{FeedRef, MsgRef};
+use std::{net::SocketAddr, time::Duration};
+
+use crate::Error;
+
+const FEED_PAGE_SIZE: i64 = 20;
+
+// Requests are served from read handles while the db keeps indexing new log entries.
+pub async fn serve(
+    mut db: Database,
+    addr: SocketAddr,
+    chunk_size: u64,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    let app = Router::new()
+        .route("/message/:id", get(get_message))
+        .route("/feed/:id", get(get_feed))
+        .with_state(db.reader());
+
+    eprintln!("Listening on http://{}", addr);
+    let server = Server::try_bind(&addr)
+        .map_err(Error::Serve)?
+        .serve(app.into_make_service());
+
+    let indexer = async {
+        let msgs = db.tail(chunk_size, poll_interval);
+        pin_mut!(msgs);
+        while let Some(msg) = msgs.next().await {
+            msg?;
+        }
+        Ok(())
+    };
+
+    tokio::try_join!(async { server.await.map_err(Error::Serve) }, indexer)?;
+
+    Ok(())
+}
+
+async fn get_message(State(db): State<DatabaseReader>, Path(id): Path<String>) -> Response {
+    let Ok(msg_ref) = MsgRef::from_urlsafe_data(&id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let msg = match db.get_msg(msg_ref).await {
+        Ok(Some(msg)) => msg,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return server_error(err),
+    };
+    match db.get_post_views(vec![msg]).await {
+        Ok(posts) => Html(render_posts(posts)).into_response(),
+        Err(err) => server_error(err),
+    }
+}
+
+async fn get_feed(State(db): State<DatabaseReader>, Path(id): Path<String>) -> Response {
+    let Ok(feed_ref) = FeedRef::from_urlsafe_data(&id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let result = db
+        .get_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+            feed_ref: &feed_ref,
+            content_type: Some("post"),
+            page_size: FEED_PAGE_SIZE,
+            less_than_feed_seq: i64::MAX,
+            is_decrypted: false,
+        })
+        .await;
+
+    let result = match result {
+        Ok(msgs) => db.get_post_views(msgs).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(posts) => Html(render_posts(posts)).into_response(),
+        Err(err) => server_error(err),
+    }
+}
+
+fn render_posts(posts: Vec<PostView>) -> String {
+    posts
+        .into_iter()
+        .map(|post| match render_post(post.msg, post.content) {
+            Ok(html) => html.to_string(),
+            Err(err) => match err {},
+        })
+        .collect()
+}
+
+fn server_error(err: ssb_db::Error) -> Response {
+    eprintln!("{}", err);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
@@ -0,0 +1,285 @@
+// Decryption of box2 envelopes, the encryption used by private groups.
+//
+// https://github.com/ssbc/envelope-spec
+
+use curve25519_dalek::{edwards::CompressedEdwardsY, montgomery::MontgomeryPoint, scalar::Scalar};
+use hkdf::Hkdf;
+use poly1305::{universal_hash::KeyInit, Poly1305};
+use salsa20::{
+    cipher::{KeyIvInit, StreamCipher},
+    XSalsa20,
+};
+use sha2::{Digest, Sha256, Sha512};
+use ssb_ref::{FeedRef, MsgRef};
+
+pub(crate) type GroupKey = [u8; 32];
+
+const GROUP_KEY_SCHEME: &str = "envelope-large-symmetric-group";
+const DM_KEY_SCHEME: &str = "envelope-id-based-dm-converted-ed25519";
+
+// Senders add at most this many recipients, so there are no more key slots to try
+const MAX_KEY_SLOTS: usize = 16;
+
+const HEADER_BOX_LEN: usize = 32;
+const KEY_SLOT_LEN: usize = 32;
+const MAC_LEN: usize = 16;
+
+// Type-format-key prefixes
+const FEED_TF: [u8; 2] = [0, 0];
+const MSG_TF: [u8; 2] = [1, 0];
+const DH_TF: [u8; 2] = [3, 0];
+
+// A key that may open one of the key slots of an envelope
+pub(crate) struct RecpKey {
+    key: [u8; 32],
+    scheme: &'static str,
+}
+
+impl RecpKey {
+    pub fn group(group_key: &GroupKey) -> Self {
+        Self {
+            key: *group_key,
+            scheme: GROUP_KEY_SCHEME,
+        }
+    }
+
+    // The key shared by our feed and another feed for direct messages between them
+    pub fn dm(my_seed: &[u8], my_public: &[u8], your_feed: &FeedRef) -> Option<Self> {
+        Some(Self {
+            key: dm_key(my_seed, my_public, your_feed.as_bytes())?,
+            scheme: DM_KEY_SCHEME,
+        })
+    }
+}
+
+// Open an envelope published by `author` after the msg `previous`, trying each key on each
+// key slot.
+pub(crate) fn unbox2(
+    envelope: &[u8],
+    author: &FeedRef,
+    previous: Option<&MsgRef>,
+    keys: &[RecpKey],
+) -> Option<Vec<u8>> {
+    let context = InfoContext::new(author, previous);
+    let header_box = envelope.get(..HEADER_BOX_LEN)?;
+    let slots = envelope[HEADER_BOX_LEN..]
+        .chunks_exact(KEY_SLOT_LEN)
+        .take(MAX_KEY_SLOTS);
+
+    for slot in slots {
+        for key in keys {
+            let slot_key = context.derive(&key.key, &[b"slot_key", key.scheme.as_bytes()]);
+            let mut msg_key = [0; 32];
+            for (index, byte) in msg_key.iter_mut().enumerate() {
+                *byte = slot[index] ^ slot_key[index];
+            }
+
+            let read_key = context.derive(&msg_key, &[b"read_key"]);
+            let header_key = context.derive(&read_key, &[b"header_key"]);
+            let Some(header) = secretbox_open(&header_key, header_box) else {
+                continue;
+            };
+
+            // the header starts with the offset of the body box
+            let offset = u16::from_le_bytes([header[0], header[1]]) as usize;
+            let body_key = context.derive(&read_key, &[b"body_key"]);
+            return secretbox_open(&body_key, envelope.get(offset..)?);
+        }
+    }
+
+    None
+}
+
+struct InfoContext {
+    feed_id: Vec<u8>,
+    prev_msg_id: Vec<u8>,
+}
+
+impl InfoContext {
+    fn new(author: &FeedRef, previous: Option<&MsgRef>) -> Self {
+        let feed_id = [&FEED_TF[..], author.as_bytes()].concat();
+        // the first msg of a feed uses a zeroed msg id
+        let prev_msg_id = match previous {
+            Some(previous) => [&MSG_TF[..], previous.as_bytes()].concat(),
+            None => [&MSG_TF[..], &[0; 32]].concat(),
+        };
+        Self {
+            feed_id,
+            prev_msg_id,
+        }
+    }
+
+    fn derive(&self, secret: &[u8; 32], labels: &[&[u8]]) -> [u8; 32] {
+        let mut info: Vec<&[u8]> = vec![b"envelope", &self.feed_id, &self.prev_msg_id];
+        info.extend_from_slice(labels);
+
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::from_prk(secret)
+            .expect("32 bytes is a valid prk length")
+            .expand(&slp_encode(&info), &mut key)
+            .expect("32 bytes is a valid output length");
+        key
+    }
+}
+
+fn dm_key(my_seed: &[u8], my_public: &[u8], your_public: &[u8]) -> Option<[u8; 32]> {
+    let my_dh_secret = ed25519_seed_to_curve25519(my_seed);
+    let my_dh_public = ed25519_public_to_curve25519(my_public)?;
+    let your_dh_public = ed25519_public_to_curve25519(your_public)?;
+    let shared_secret = (MontgomeryPoint(your_dh_public) * Scalar::from_bits(my_dh_secret)).0;
+
+    let salt = Sha256::digest(b"envelope-dm-v1-extract-salt");
+    let mut keys = [
+        [&DH_TF[..], &my_dh_public, &FEED_TF, my_public].concat(),
+        [&DH_TF[..], &your_dh_public, &FEED_TF, your_public].concat(),
+    ];
+    // both feeds must derive the same key
+    keys.sort();
+    let info = slp_encode(&[b"envelope-ssb-dm-v1/key", &keys[0], &keys[1]]);
+
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(Some(&salt), &shared_secret)
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid output length");
+    Some(key)
+}
+
+fn ed25519_seed_to_curve25519(seed: &[u8]) -> [u8; 32] {
+    let hash = Sha512::digest(seed);
+    let mut secret = [0; 32];
+    secret.copy_from_slice(&hash[..32]);
+    secret[0] &= 248;
+    secret[31] &= 127;
+    secret[31] |= 64;
+    secret
+}
+
+fn ed25519_public_to_curve25519(public: &[u8]) -> Option<[u8; 32]> {
+    let point = CompressedEdwardsY::from_slice(public).decompress()?;
+    Some(point.to_montgomery().0)
+}
+
+// Shallow length-prefixed encoding
+fn slp_encode(items: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for item in items {
+        encoded.extend_from_slice(&(item.len() as u16).to_le_bytes());
+        encoded.extend_from_slice(item);
+    }
+    encoded
+}
+
+// Open a nacl secretbox sealed with a zero nonce, every key in an envelope is used once.
+pub(crate) fn secretbox_open(key: &[u8; 32], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < MAC_LEN {
+        return None;
+    }
+    let (mac, ciphertext) = sealed.split_at(MAC_LEN);
+
+    let mut cipher = XSalsa20::new(key.into(), &[0; 24].into());
+    let mut poly_key = [0; 32];
+    cipher.apply_keystream(&mut poly_key);
+    let tag = Poly1305::new(&poly_key.into()).compute_unpadded(ciphertext);
+    let difference = tag
+        .iter()
+        .zip(mac)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    if difference != 0 {
+        return None;
+    }
+
+    // the rest of the keystream encrypts the plaintext
+    let mut plaintext = ciphertext.to_vec();
+    cipher.apply_keystream(&mut plaintext);
+    Some(plaintext)
+}
+
+// Seal an envelope with a slot for each key, as other feeds publish private msgs.
+#[cfg(test)]
+pub(crate) fn box2(
+    plaintext: &[u8],
+    author: &FeedRef,
+    previous: Option<&MsgRef>,
+    keys: &[RecpKey],
+) -> Vec<u8> {
+    let context = InfoContext::new(author, previous);
+    let msg_key = [7; 32];
+    let read_key = context.derive(&msg_key, &[b"read_key"]);
+
+    let mut header = [0; 16];
+    let offset = (HEADER_BOX_LEN + KEY_SLOT_LEN * keys.len()) as u16;
+    header[..2].copy_from_slice(&offset.to_le_bytes());
+    let mut envelope = secretbox_seal(&context.derive(&read_key, &[b"header_key"]), &header);
+    for key in keys {
+        let slot_key = context.derive(&key.key, &[b"slot_key", key.scheme.as_bytes()]);
+        envelope.extend(msg_key.iter().zip(slot_key).map(|(a, b)| a ^ b));
+    }
+    envelope.extend(secretbox_seal(
+        &context.derive(&read_key, &[b"body_key"]),
+        plaintext,
+    ));
+    envelope
+}
+
+// Seal a nacl secretbox with a zero nonce, so each key must only seal one plaintext.
+pub(crate) fn secretbox_seal(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let mut cipher = XSalsa20::new(key.into(), &[0; 24].into());
+    let mut poly_key = [0; 32];
+    cipher.apply_keystream(&mut poly_key);
+    let mut ciphertext = plaintext.to_vec();
+    cipher.apply_keystream(&mut ciphertext);
+    let tag = Poly1305::new(&poly_key.into()).compute_unpadded(&ciphertext);
+    [tag.as_slice(), &ciphertext].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::{general_purpose::STANDARD as b64, Engine};
+    use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+
+    fn public_key(seed: &[u8; 32]) -> [u8; 32] {
+        let secret = Scalar::from_bits(ed25519_seed_to_curve25519(seed));
+        (&ED25519_BASEPOINT_TABLE * &secret).compress().to_bytes()
+    }
+
+    fn feed(public: &[u8; 32]) -> FeedRef {
+        FeedRef::from_string(format!("@{}.ed25519", b64.encode(public))).unwrap()
+    }
+
+    #[test]
+    fn dm_key_is_shared_by_both_feeds() {
+        let (alice_seed, bob_seed) = ([1; 32], [2; 32]);
+        let (alice, bob) = (public_key(&alice_seed), public_key(&bob_seed));
+        assert_eq!(
+            dm_key(&alice_seed, &alice, &bob),
+            dm_key(&bob_seed, &bob, &alice)
+        );
+    }
+
+    #[test]
+    fn unbox2_tries_every_key_slot() {
+        let (alice_seed, bob_seed) = ([1; 32], [2; 32]);
+        let (alice, bob) = (public_key(&alice_seed), public_key(&bob_seed));
+        let group_key = [3; 32];
+        let to_bob = RecpKey::dm(&alice_seed, &alice, &feed(&bob)).unwrap();
+        let envelope = box2(
+            b"hi",
+            &feed(&alice),
+            None,
+            &[RecpKey::group(&group_key), to_bob],
+        );
+
+        let from_alice = RecpKey::dm(&bob_seed, &bob, &feed(&alice)).unwrap();
+        let opened = unbox2(&envelope, &feed(&alice), None, &[from_alice]);
+        assert_eq!(opened.as_deref(), Some(&b"hi"[..]));
+        let opened = unbox2(
+            &envelope,
+            &feed(&alice),
+            None,
+            &[RecpKey::group(&group_key)],
+        );
+        assert_eq!(opened.as_deref(), Some(&b"hi"[..]));
+        assert!(unbox2(&envelope, &feed(&alice), None, &[RecpKey::group(&[4; 32])]).is_none());
+    }
+}
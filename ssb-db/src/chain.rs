@@ -0,0 +1,171 @@
+use ssb_ref::MsgRef;
+
+use crate::sql::{ChainIssueKind, ChainIssueRow};
+
+// A problem with how a feed's msgs link together
+#[derive(Debug)]
+pub enum ChainIssue {
+    // The msgs from_seq..=to_seq are not in the index
+    Gap {
+        from_seq: u64,
+        to_seq: u64,
+    },
+    // The msg's `previous` is not the key of the msg before it
+    BrokenLink {
+        feed_seq: u64,
+        msg_ref: MsgRef,
+    },
+    // More than one msg claims the same sequence
+    Fork {
+        feed_seq: u64,
+        msg_refs: Vec<MsgRef>,
+    },
+}
+
+pub(crate) struct ChainMsg {
+    pub feed_seq: u64,
+    pub msg_ref_id: i64,
+    pub key: String,
+    pub previous: Option<String>,
+}
+
+// Check that a feed's msgs, ordered by sequence, form a single unbroken chain.
+pub(crate) fn check_chain(msgs: &[ChainMsg]) -> Vec<ChainIssueRow> {
+    let mut issues = Vec::new();
+
+    let mut expected_seq = 1;
+    let mut previous_keys: Vec<&str> = Vec::new();
+    let mut start = 0;
+    while start < msgs.len() {
+        let feed_seq = msgs[start].feed_seq;
+        let len = msgs[start..]
+            .iter()
+            .take_while(|msg| msg.feed_seq == feed_seq)
+            .count();
+        let same_seq = &msgs[start..start + len];
+        start += len;
+
+        let is_after_gap = feed_seq > expected_seq;
+        if is_after_gap {
+            issues.push(ChainIssueRow {
+                kind: ChainIssueKind::Gap,
+                from_feed_seq: expected_seq,
+                to_feed_seq: feed_seq - 1,
+                msg_ref_id: None,
+            });
+        }
+
+        if same_seq.len() > 1 {
+            issues.extend(same_seq.iter().map(|msg| ChainIssueRow {
+                kind: ChainIssueKind::Fork,
+                from_feed_seq: feed_seq,
+                to_feed_seq: feed_seq,
+                msg_ref_id: Some(msg.msg_ref_id),
+            }));
+        }
+
+        // the link to a missing msg can't be checked
+        if !is_after_gap {
+            for msg in same_seq {
+                let is_linked = match &msg.previous {
+                    None => feed_seq == 1,
+                    Some(previous) => previous_keys.contains(&previous.as_str()),
+                };
+                if !is_linked {
+                    issues.push(ChainIssueRow {
+                        kind: ChainIssueKind::BrokenLink,
+                        from_feed_seq: feed_seq,
+                        to_feed_seq: feed_seq,
+                        msg_ref_id: Some(msg.msg_ref_id),
+                    });
+                }
+            }
+        }
+
+        expected_seq = feed_seq + 1;
+        previous_keys = same_seq.iter().map(|msg| msg.key.as_str()).collect();
+    }
+
+    issues
+}
+
+// Group the stored rows of a feed back into issues, forks by sequence.
+pub(crate) fn to_chain_issues(rows: Vec<(ChainIssueRow, Option<MsgRef>)>) -> Vec<ChainIssue> {
+    let mut issues: Vec<ChainIssue> = Vec::new();
+    for (row, msg_ref) in rows {
+        match (row.kind, msg_ref) {
+            (ChainIssueKind::Gap, _) => issues.push(ChainIssue::Gap {
+                from_seq: row.from_feed_seq,
+                to_seq: row.to_feed_seq,
+            }),
+            (ChainIssueKind::BrokenLink, Some(msg_ref)) => issues.push(ChainIssue::BrokenLink {
+                feed_seq: row.from_feed_seq,
+                msg_ref,
+            }),
+            (ChainIssueKind::Fork, Some(msg_ref)) => match issues.last_mut() {
+                Some(ChainIssue::Fork { feed_seq, msg_refs }) if *feed_seq == row.from_feed_seq => {
+                    msg_refs.push(msg_ref)
+                }
+                _ => issues.push(ChainIssue::Fork {
+                    feed_seq: row.from_feed_seq,
+                    msg_refs: vec![msg_ref],
+                }),
+            },
+            _ => {}
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(feed_seq: u64, key: &str, previous: Option<&str>) -> ChainMsg {
+        ChainMsg {
+            feed_seq,
+            msg_ref_id: feed_seq as i64 * 10 + key.len() as i64,
+            key: key.to_string(),
+            previous: previous.map(|previous| previous.to_string()),
+        }
+    }
+
+    fn kinds(issues: &[ChainIssueRow]) -> Vec<(ChainIssueKind, u64, u64)> {
+        issues
+            .iter()
+            .map(|issue| (issue.kind, issue.from_feed_seq, issue.to_feed_seq))
+            .collect()
+    }
+
+    #[test]
+    fn check_chain_accepts_valid_feed() {
+        let msgs = [
+            msg(1, "a", None),
+            msg(2, "b", Some("a")),
+            msg(3, "c", Some("b")),
+        ];
+        assert!(check_chain(&msgs).is_empty());
+    }
+
+    #[test]
+    fn check_chain_finds_gaps_broken_links_and_forks() {
+        let msgs = [
+            msg(2, "b", Some("a")),
+            msg(3, "c", Some("x")),
+            msg(4, "d", Some("c")),
+            msg(4, "dd", Some("c")),
+            msg(5, "e", Some("dd")),
+            msg(8, "h", Some("g")),
+        ];
+        assert_eq!(
+            kinds(&check_chain(&msgs)),
+            vec![
+                (ChainIssueKind::Gap, 1, 1),
+                (ChainIssueKind::BrokenLink, 3, 3),
+                (ChainIssueKind::Fork, 4, 4),
+                (ChainIssueKind::Fork, 4, 4),
+                (ChainIssueKind::Gap, 6, 7),
+            ]
+        );
+    }
+}
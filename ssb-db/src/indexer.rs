@@ -0,0 +1,166 @@
+use futures::future::BoxFuture;
+use serde_json::Value;
+use sqlx::{Error as SqlError, SqliteConnection};
+use ssb_msg::{Msg, MsgContent};
+use std::sync::Arc;
+
+// Keeps tables in the sqlite db up to date with the content of indexed msgs.
+//
+// Indexers are registered with `Database::new` and run after the built-in ones for posts,
+// contacts, votes, abouts and groups. Their tables and indices are created every time the
+// db is opened, so use `IF NOT EXISTS`. An indexer added to an existing db only sees msgs
+// indexed from then on, until it is rebuilt with `Database::rebuild_index`.
+pub trait Indexer: Send + Sync {
+    // Unique among the registered indexers
+    fn name(&self) -> &str;
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), SqlError>>;
+
+    fn create_indices<'c>(
+        &'c self,
+        _connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), SqlError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // Called for every msg whose content could be read, inside the transaction of the batch
+    // the msg is indexed in. An error rolls back the whole batch.
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), SqlError>>;
+
+    // Delete everything the indexer has stored so every msg can be indexed again. Indexers
+    // that return `None` can't be rebuilt.
+    fn clear<'c>(
+        &'c self,
+        _connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), SqlError>>> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IndexedMsg<'a> {
+    // Private msgs have their decrypted content
+    pub msg: &'a Msg<Value>,
+    // `MsgContent::Unknown` for content types ssb-db doesn't parse, read those from `msg`
+    pub content: &'a MsgContent,
+    // The id of the msg in the `msg_refs` table, to reference it from other tables
+    pub msg_ref_id: i64,
+    pub is_decrypted: bool,
+}
+
+// Hand a msg to each indexer in the order they were registered.
+pub(crate) async fn index_msg(
+    connection: &mut SqliteConnection,
+    indexers: &[Arc<dyn Indexer>],
+    msg: IndexedMsg<'_>,
+) -> Result<(), SqlError> {
+    for indexer in indexers {
+        indexer.index_msg(&mut *connection, msg).await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn create_indexer_schema(
+    connection: &mut SqliteConnection,
+    indexers: &[Arc<dyn Indexer>],
+) -> Result<(), SqlError> {
+    for indexer in indexers {
+        indexer.create_tables(&mut *connection).await?;
+        indexer.create_indices(&mut *connection).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::SelectMsgsByHashtagOptions;
+    use serde_json::{from_value, json};
+
+    #[tokio::test]
+    async fn custom_indexers_index_msgs_into_their_own_tables() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), vec![Arc::new(PostTextIndexer)]).await;
+        db.publish(&alice, &post("first")).await.unwrap();
+        db.publish(&alice, &post("second")).await.unwrap();
+
+        let mut connection = db.reader().acquire().await.unwrap();
+        assert_eq!(
+            select_post_texts(&mut connection).await,
+            ["first", "second"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rebuilds_indexers_registered_on_an_existing_db() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.publish(&alice, &post("before")).await.unwrap();
+        drop(db);
+
+        let mut db = open_db(&dir, Vec::new(), vec![Arc::new(PostTextIndexer)]).await;
+        let reader = db.reader();
+        let mut connection = reader.acquire().await.unwrap();
+        assert!(select_post_texts(&mut connection).await.is_empty());
+        drop(connection);
+
+        assert!(db.rebuild_index("post_texts").await.unwrap());
+        let mut connection = reader.acquire().await.unwrap();
+        assert_eq!(select_post_texts(&mut connection).await, ["before"]);
+    }
+
+    #[tokio::test]
+    async fn rebuilds_the_posts_index() {
+        let dir = temp_dir();
+        let (alice, bob) = (keypair(1), keypair(2));
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let root = db.publish(&alice, &post("a #rust root")).await.unwrap();
+        let reply = from_value(json!({
+            "type": "post",
+            "text": "reply",
+            "root": root.key.to_string(),
+            "branch": root.key.to_string(),
+        }))
+        .unwrap();
+        db.publish(&bob, &reply).await.unwrap();
+
+        assert!(db.rebuild_index("posts").await.unwrap());
+        let reader = db.reader();
+        let threads = reader.get_active_threads(None, 10).await.unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].reply_count, 1);
+        let thread = reader.get_thread(&root.key).await.unwrap().unwrap();
+        assert_eq!(thread.replies.len(), 1);
+        let tagged = reader
+            .get_msgs_by_hashtag(SelectMsgsByHashtagOptions {
+                hashtag: "rust",
+                less_than_log_seq: None,
+                page_size: 10,
+            })
+            .await
+            .unwrap();
+        assert_eq!(tagged.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn doesnt_rebuild_unknown_indexers_or_ones_that_cant_be_cleared() {
+        let dir = temp_dir();
+        let mut db = open_db(&dir, Vec::new(), vec![Arc::new(PostTextIndexer)]).await;
+
+        assert!(!db.rebuild_index("unknown").await.unwrap());
+        // the built-in groups indexer has no `clear`
+        assert!(!db.rebuild_index("groups").await.unwrap());
+    }
+}
@@ -1,25 +1,70 @@
 use base64::engine::{general_purpose::STANDARD as b64, Engine};
 use flumedb::FlumeOffsetLogError;
 use flumedb::{FlumeLog, IterAtOffset, OffsetLog, Sequence};
+use futures::stream::{self, Stream};
 use itertools::Itertools;
 use log::{info, trace};
 use private_box::Keypair;
 use serde_json::{from_value, Error as JsonError, Value};
 use sqlx::{Connection, SqliteConnection};
-use ssb_msg::{Msg, MsgContent};
+use ssb_msg::{keypair_feed_ref, to_log_entry, BuildError, Msg, MsgBuilder, MsgContent};
 use ssb_ref::{FeedRef, MsgRef};
-use std::path::Path;
-use std::{fs::OpenOptions, io};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{
+    fs::{metadata, File, OpenOptions},
+    io, iter,
+};
 use thiserror::Error as ThisError;
-
+use tokio::{sync::RwLock, time::sleep};
+
+mod box2;
+mod chain;
+mod indexer;
+mod profile;
+mod query;
+mod reader;
+mod secret;
 pub mod sql;
-pub use sql::SelectAllMsgsByFeedOptions;
+#[cfg(test)]
+mod test_utils;
+mod thread;
+mod views;
+use box2::{unbox2, GroupKey, RecpKey};
+pub use chain::ChainIssue;
+use chain::{check_chain, to_chain_issues, ChainMsg};
+use indexer::{create_indexer_schema, index_msg};
+pub use indexer::{IndexedMsg, Indexer};
+pub use profile::{GivenName, Profile, ProfileName};
+pub use query::{InvalidCursor, MsgCursor, MsgOrder, MsgQuery};
+pub use reader::{
+    ConversationMsg, DatabaseReader, Mention, MsgPage, SearchResult, TimelineMsg, VotedMsg,
+};
+pub use secret::{load_secret_file, SecretError};
 use sql::*;
-
+pub use sql::{
+    content_key_from_secret, ContentKey, ExpressionCount, FeedHops, Group, HashtagCount,
+    MentionKind, MsgVote, PrivateConversation, ReceivedCursor, SearchOptions,
+    SelectAllMsgsByFeedOptions, SelectMsgsByHashtagOptions, Stats, ThreadCursor, ThreadSummary,
+    TimelineOptions,
+};
+pub use thread::{Thread, ThreadNode};
+pub use views::{ContactView, PostView};
+
+// The indexer, the only writer to the sqlite db. Use `reader` to get handles for querying.
 pub struct Database {
     sql: SqliteConnection,
-    log: OffsetLog<u32>,
+    log: Arc<RwLock<OffsetLog<u32>>>,
+    log_path: PathBuf,
+    log_len: u64,
     keys: Vec<Keypair>,
+    content_key: Option<ContentKey>,
+    group_keys: Vec<GroupKey>,
+    // The built-in indexers followed by the ones registered with `new`
+    indexers: Vec<Arc<dyn Indexer>>,
+    reader: DatabaseReader,
 }
 
 #[derive(Debug, ThisError)]
@@ -28,52 +73,101 @@ pub enum Error {
     RemoveFile(#[source] io::Error),
     #[error("Failed to open file, cause: {0}")]
     OpenFile(#[source] io::Error),
+    #[error("Failed to read file metadata, cause: {0}")]
+    FileMetadata(#[source] io::Error),
     #[error("Failed to create log from file, cause: {0}")]
     LogFromFile(#[source] FlumeOffsetLogError),
     #[error("Failed to get from log, cause: {0}")]
     LogGet(#[source] FlumeOffsetLogError),
+    #[error("Failed to append to log, cause: {0}")]
+    LogAppend(#[source] FlumeOffsetLogError),
+    #[error("Failed to build msg, cause: {0}")]
+    Build(#[from] BuildError),
     #[error("Json error, cause: {0}")]
     Json(#[from] serde_json::Error),
     #[error("Sql error, cause: {0}")]
     Sql(#[from] sqlx::Error),
     #[error("Sql database failed integrity check")]
     SqlIntegrityCheckFailure {},
+    #[error("Msg cursor is for a query with a different order")]
+    CursorOrderMismatch {},
 }
 
 impl Database {
+    // `indexers` add tables of their own, kept up to date as msgs are indexed.
+    //
+    // Private msgs that `keys` decrypt have their content stored in the sqlite db, sealed
+    // with `content_key` if there is one. The same key must be passed every time the db is
+    // opened, contents it can't open read as still encrypted. What the indexers store of
+    // decrypted msgs is never sealed, so the db must be kept as private as the keys.
     pub async fn new<LogPath, SqlPath>(
         log_path: LogPath,
         sql_path: SqlPath,
         keys: Vec<Keypair>,
+        content_key: Option<ContentKey>,
+        indexers: Vec<Arc<dyn Indexer>>,
     ) -> Result<Self, Error>
     where
         LogPath: AsRef<Path>,
         SqlPath: AsRef<Path>,
     {
-        let log_file = OpenOptions::new()
-            .read(true)
-            .write(false)
-            .create(false)
-            .open(&log_path)
-            .map_err(Error::OpenFile)?;
-        let log = OffsetLog::<u32>::from_file(log_file).map_err(Error::LogFromFile)?;
+        let log_path = log_path.as_ref().to_path_buf();
+        let (log, log_len) = open_log(&log_path)?;
 
-        let mut sql = create_connection(&sql_path).await?;
+        let custom_indexers = indexers;
+        let indexers: Vec<Arc<dyn Indexer>> = builtin_indexers()
+            .into_iter()
+            .chain(custom_indexers.iter().cloned())
+            .collect();
 
-        if let Ok(false) = is_db_up_to_date(&mut sql).await {
-            info!("sqlite db is out of date. Deleting db and it will be rebuilt.");
-            std::fs::remove_file(&sql_path).map_err(Error::RemoveFile)?;
+        let mut sql = create_connection(&sql_path).await?;
 
-            sql = create_connection(&sql_path).await?;
-            setup_new_db(&mut sql).await?;
+        // migrations index the msgs they decrypt with every indexer, so the tables of custom
+        // indexers must exist first. The built-in ones are created by the migrations.
+        create_indexer_schema(&mut sql, &custom_indexers).await?;
+        match migrate_db(&mut sql, &log, &keys, content_key.as_ref(), &indexers).await? {
+            MigrateOutcome::UpToDate => {}
+            MigrateOutcome::Migrated { from, to } => {
+                info!("sqlite db migrated from version {} to {}.", from, to);
+            }
+            MigrateOutcome::NeedsRebuild => {
+                info!("sqlite db is missing or can't be migrated. Deleting db and it will be rebuilt.");
+                // closing the last connection removes the WAL files by name, so it must
+                // happen before the new db creates its own
+                sql.close().await?;
+                std::fs::remove_file(&sql_path).map_err(Error::RemoveFile)?;
+
+                sql = create_connection(&sql_path).await?;
+                setup_new_db(&mut sql, &indexers).await?;
+            }
         }
         setup_db(&mut sql).await?;
+        create_indexer_schema(&mut sql, &indexers).await?;
+        let group_keys = select_group_keys(&mut sql).await?;
+
+        let log = Arc::new(RwLock::new(log));
+        let pool = create_read_pool(&sql_path).await?;
+        let reader = DatabaseReader::new(pool, log.clone(), content_key);
+
+        Ok(Self {
+            sql,
+            log,
+            log_path,
+            log_len,
+            keys,
+            content_key,
+            group_keys,
+            indexers,
+            reader,
+        })
+    }
 
-        Ok(Self { sql, log, keys })
+    pub fn reader(&self) -> DatabaseReader {
+        self.reader.clone()
     }
 
     pub async fn get_log_latest(&self) -> Option<Sequence> {
-        self.log.latest()
+        self.log.read().await.latest()
     }
 
     pub async fn get_sql_latest(&mut self) -> Result<Option<Sequence>, Error> {
@@ -81,6 +175,62 @@ impl Database {
     }
 
     pub async fn process(&mut self, chunk_size: u64) -> Result<(), Error> {
+        self.process_chunk(chunk_size).await?;
+
+        Ok(())
+    }
+
+    // Follow appends to the offset log, yielding each msg once it has been indexed.
+    //
+    // The log is checked for new entries every `poll_interval`. The stream ends after
+    // yielding the first error.
+    pub fn tail(
+        &mut self,
+        chunk_size: u64,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = Result<Msg<Value>, Error>> + '_ {
+        let pending: VecDeque<Msg<Value>> = VecDeque::new();
+        stream::unfold(Some((self, pending)), move |state| async move {
+            let (db, mut pending) = state?;
+            loop {
+                if let Some(msg) = pending.pop_front() {
+                    return Some((Ok(msg), Some((db, pending))));
+                }
+
+                let indexed = match db.process_chunk(chunk_size).await {
+                    Ok(indexed) => indexed,
+                    Err(err) => return Some((Err(err), None)),
+                };
+                if !indexed.is_empty() {
+                    pending.extend(indexed);
+                    continue;
+                }
+
+                match db.reload_log().await {
+                    Ok(true) => {}
+                    Ok(false) => sleep(poll_interval).await,
+                    Err(err) => return Some((Err(err), None)),
+                }
+            }
+        })
+    }
+
+    // Re-open the offset log if it has grown since it was last opened.
+    async fn reload_log(&mut self) -> Result<bool, Error> {
+        let log_len = metadata(&self.log_path).map_err(Error::FileMetadata)?.len();
+        if log_len <= self.log_len {
+            return Ok(false);
+        }
+
+        trace!("offset log grew from {} to {} bytes", self.log_len, log_len);
+        let (log, log_len) = open_log(&self.log_path)?;
+        *self.log.write().await = log;
+        self.log_len = log_len;
+
+        Ok(true)
+    }
+
+    async fn process_chunk(&mut self, chunk_size: u64) -> Result<Vec<Msg<Value>>, Error> {
         let latest = self.get_sql_latest().await?;
 
         //If the latest is 0, we haven't got anything in the db. Don't skip the very first
@@ -90,8 +240,9 @@ impl Database {
             Some(_) => 1,
         };
 
-        for chunk in self
-            .log
+        let log = self.log.read().await;
+        let mut indexed = Vec::new();
+        for chunk in log
             .iter_at_offset(latest.unwrap_or(0))
             .skip(num_to_skip as usize)
             .take(chunk_size as usize)
@@ -100,84 +251,316 @@ impl Database {
             .into_iter()
         {
             let vec = chunk.collect_vec();
-            append_batch(&mut self.sql, &self.keys, &vec).await?;
+            indexed.extend(
+                append_batch(
+                    &mut self.sql,
+                    &self.indexers,
+                    self.content_key.as_ref(),
+                    &self.keys,
+                    &self.group_keys,
+                    &vec,
+                )
+                .await?,
+            );
+        }
+
+        // msgs of groups we were just added to may already be indexed
+        decrypt_indexed_msgs(
+            &mut self.sql,
+            &log,
+            &self.indexers,
+            self.content_key.as_ref(),
+            &[],
+            &mut self.group_keys,
+        )
+        .await?;
+
+        Ok(indexed)
+    }
+
+    // Sign new content as the next msg of the keypair's feed, append it to the offset log
+    // and index it, along with any entries other writers appended before it.
+    //
+    // The sequence and previous key come from the index, so anything else appending to
+    // the log must not be publishing for the same feed at the same time.
+    pub async fn publish(
+        &mut self,
+        keypair: &ssb_msg::Keypair,
+        content: &MsgContent,
+    ) -> Result<Msg<Value>, Error> {
+        // catch up so the latest msg of the feed is indexed
+        self.reload_log().await?;
+        while !self.process_chunk(u64::MAX).await?.is_empty() {}
+
+        let builder = MsgBuilder::new(content)?;
+        let author = keypair_feed_ref(keypair);
+        let builder = match select_latest_msg_ref_by_feed(&mut self.sql, &author).await? {
+            Some((previous, sequence)) => builder.previous(previous, sequence),
+            None => builder,
+        };
+        let msg = builder.sign(keypair);
+
+        let entry = serde_json::to_vec(&to_log_entry(&msg))?;
+        self.append_to_log(&entry).await?;
+
+        // other writers may have appended since the catch up, their entries come before ours
+        let mut published = None;
+        loop {
+            let indexed = self.process_chunk(u64::MAX).await?;
+            if indexed.is_empty() {
+                break;
+            }
+            published = indexed
+                .into_iter()
+                .find(|indexed| indexed.key.to_string() == msg.key.to_string())
+                .or(published);
         }
 
+        Ok(published.unwrap_or(msg))
+    }
+
+    // Append an entry through the shared log, re-opened for writing so it appends at the
+    // current end of the file rather than where the log ended when it was last opened.
+    //
+    // Readers share the log, so the entry must be appended before its row is committed, or
+    // a reader could find the row and fail to read the entry.
+    async fn append_to_log(&mut self, entry: &[u8]) -> Result<(), Error> {
+        let mut log = self.log.write().await;
+        let mut writable_log = OffsetLog::<u32>::new(&self.log_path).map_err(Error::LogFromFile)?;
+        writable_log.append(entry).map_err(Error::LogAppend)?;
+        *log = writable_log;
+        self.log_len = metadata(&self.log_path).map_err(Error::FileMetadata)?.len();
+
         Ok(())
     }
 
-    // queries
+    // Check that the msgs of every indexed feed form a chain and store the issues found.
+    // Returns the number of feeds with issues.
+    pub async fn validate_feeds(&mut self) -> Result<usize, Error> {
+        let feed_ref_ids = select_feed_ref_ids_with_msgs(&mut self.sql).await?;
+        let mut invalid_feeds_count = 0;
+        for feed_ref_id in feed_ref_ids {
+            if self.validate_feed_id(feed_ref_id).await? {
+                invalid_feeds_count += 1;
+            }
+        }
+        Ok(invalid_feeds_count)
+    }
 
-    pub async fn get_msg(&mut self, msg_ref: MsgRef) -> Result<Option<Msg<Value>>, Error> {
-        let log_seq_opt = get_msg_log_seq(&mut self.sql, &msg_ref).await?;
-        if let Some(log_seq) = log_seq_opt {
-            let bytes = self.log.get(log_seq).map_err(Error::LogGet)?;
-            let msg: Msg<Value> = serde_json::from_slice(bytes.as_slice())?;
-            Ok(Some(msg))
-        } else {
-            Ok(None)
+    pub async fn validate_feed(&mut self, feed_ref: &FeedRef) -> Result<Vec<ChainIssue>, Error> {
+        if let Some(feed_ref_id) = find_feed_ref(&mut self.sql, feed_ref).await? {
+            self.validate_feed_id(feed_ref_id).await?;
         }
+        let rows = select_chain_issues_by_feed(&mut self.sql, feed_ref).await?;
+        Ok(to_chain_issues(rows))
     }
 
-    pub async fn get_all_msgs_by_feed(
-        &mut self,
-        options: SelectAllMsgsByFeedOptions<'_>,
-    ) -> Result<Vec<Msg<Value>>, Error> {
-        let log_seqs = select_all_msg_log_seqs_by_feed(&mut self.sql, options).await?;
-        let mut msgs: Vec<Msg<Value>> = Vec::new();
-        for log_seq in log_seqs {
-            let bytes = self.log.get(log_seq).map_err(Error::LogGet)?;
-            let msg: Msg<Value> = serde_json::from_slice(bytes.as_slice())?;
-            msgs.push(msg)
+    async fn validate_feed_id(&mut self, feed_ref_id: i64) -> Result<bool, Error> {
+        let chain_msgs = select_feed_chain_msgs(&mut self.sql, feed_ref_id).await?;
+        let log = self.log.read().await;
+        let mut msgs = Vec::with_capacity(chain_msgs.len());
+        for chain_msg in chain_msgs {
+            let msg = read_log_msg(&log, chain_msg.log_seq)?;
+            msgs.push(ChainMsg {
+                feed_seq: chain_msg.feed_seq,
+                msg_ref_id: chain_msg.msg_ref_id,
+                key: msg.key.to_string(),
+                previous: msg.value.previous.as_ref().map(MsgRef::to_string),
+            });
+        }
+
+        let issues = check_chain(&msgs);
+        let mut tx = self.sql.begin().await?;
+        replace_chain_issues(&mut tx, feed_ref_id, &issues).await?;
+        tx.commit().await?;
+
+        Ok(!issues.is_empty())
+    }
+
+    // Add a key to decrypt private msgs with, including the ones already indexed.
+    //
+    // Encrypted msgs none of the previous keys could decrypt are tried with the new key, and
+    // the content of those it decrypts is indexed. Returns how many msgs were decrypted.
+    pub async fn add_key(&mut self, key: Keypair) -> Result<usize, Error> {
+        let keys = [key];
+        let decrypted_count = decrypt_indexed_msgs(
+            &mut self.sql,
+            &*self.log.read().await,
+            &self.indexers,
+            self.content_key.as_ref(),
+            &keys,
+            &mut self.group_keys,
+        )
+        .await?;
+
+        let [key] = keys;
+        self.keys.push(key);
+
+        Ok(decrypted_count)
+    }
+
+    // Clear what an indexer has stored and index every readable msg with it again, e.g. after
+    // registering it on an existing db. Returns false if there is no indexer with that name
+    // or it can't be rebuilt.
+    pub async fn rebuild_index(&mut self, name: &str) -> Result<bool, Error> {
+        let Some(indexer) = self.indexers.iter().find(|indexer| indexer.name() == name) else {
+            return Ok(false);
+        };
+
+        // readers see the old index until the rebuilt one is committed
+        let mut tx = self.sql.begin().await?;
+        let log = self.log.read().await;
+        let indexers = std::slice::from_ref(indexer);
+        if !rebuild_indexers(&mut tx, &log, self.content_key.as_ref(), indexers).await? {
+            return Ok(false);
         }
-        Ok(msgs)
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+// Clear what the indexers have stored and index every readable msg with them again.
+// Returns false if one of them can't be cleared, leaving the ones before it cleared, so run
+// it in a transaction.
+pub(crate) async fn rebuild_indexers(
+    sql: &mut SqliteConnection,
+    log: &OffsetLog<u32>,
+    content_key: Option<&ContentKey>,
+    indexers: &[Arc<dyn Indexer>],
+) -> Result<bool, Error> {
+    for indexer in indexers {
+        let Some(clear) = indexer.clear(&mut *sql) else {
+            return Ok(false);
+        };
+        clear.await?;
     }
 
-    pub async fn get_max_seq_by_feed(&mut self, feed_ref: &FeedRef) -> Result<i64, Error> {
-        Ok(select_max_seq_by_feed(&mut self.sql, feed_ref).await?)
+    let mut after_log_seq = None;
+    loop {
+        let rows = select_readable_msgs(&mut *sql, content_key, after_log_seq, 1000).await?;
+        let Some((last_log_seq, ..)) = rows.last() else {
+            break;
+        };
+        after_log_seq = Some(*last_log_seq);
+
+        for (log_seq, msg_ref_id, decrypted_content) in rows {
+            let mut msg = read_log_msg(log, log_seq)?;
+            let is_decrypted = decrypted_content.is_some();
+            if let Some(content) = decrypted_content {
+                msg.value.content = content;
+                msg.value.is_private = true;
+            }
+            let Ok(content) = from_value::<MsgContent>(msg.value.content.clone()) else {
+                continue;
+            };
+
+            let indexed_msg = IndexedMsg {
+                msg: &msg,
+                content: &content,
+                msg_ref_id,
+                is_decrypted,
+            };
+            index_msg(&mut *sql, indexers, indexed_msg).await?;
+        }
     }
+
+    Ok(true)
+}
+
+pub(crate) fn read_log_msg(log: &OffsetLog<u32>, log_seq: Sequence) -> Result<Msg<Value>, Error> {
+    let bytes = log.get(log_seq).map_err(Error::LogGet)?;
+    Ok(serde_json::from_slice(bytes.as_slice())?)
+}
+
+fn open_log(log_path: &Path) -> Result<(OffsetLog<u32>, u64), Error> {
+    let log_file: File = OpenOptions::new()
+        .read(true)
+        .write(false)
+        .create(false)
+        .open(log_path)
+        .map_err(Error::OpenFile)?;
+    let log_len = log_file.metadata().map_err(Error::FileMetadata)?.len();
+    let log = OffsetLog::<u32>::from_file(log_file).map_err(Error::LogFromFile)?;
+
+    Ok((log, log_len))
 }
 
 async fn append_batch(
     sql: &mut SqliteConnection,
+    indexers: &[Arc<dyn Indexer>],
+    content_key: Option<&ContentKey>,
     secret_keys: &[Keypair],
+    group_keys: &[GroupKey],
     items: &[(Sequence, Vec<u8>)],
-) -> Result<(), Error> {
+) -> Result<Vec<Msg<Value>>, Error> {
     trace!("Start batch append");
 
+    let indexers = indexers.to_owned();
+    let content_key = content_key.copied();
     let secret_keys = secret_keys.to_owned();
+    let group_keys = group_keys.to_owned();
     let items_cloned = items.to_owned();
-    sql.transaction::<'_, _, _, Error>(move |mut conn| {
-        Box::pin(async move {
-            for item in items_cloned {
-                append_item(&mut conn, &secret_keys, &item.0, &item.1).await?;
-            }
-            Ok(())
+    let msgs = sql
+        .transaction::<'_, _, _, Error>(move |mut conn| {
+            Box::pin(async move {
+                let mut msgs = Vec::with_capacity(items_cloned.len());
+                for item in items_cloned {
+                    msgs.push(
+                        append_item(
+                            &mut conn,
+                            &indexers,
+                            content_key.as_ref(),
+                            &secret_keys,
+                            &group_keys,
+                            &item.0,
+                            &item.1,
+                        )
+                        .await?,
+                    );
+                }
+                Ok(msgs)
+            })
         })
-    })
-    .await?;
+        .await?;
 
-    Ok(())
+    Ok(msgs)
 }
 
 async fn append_item(
     sql: &mut SqliteConnection,
+    indexers: &[Arc<dyn Indexer>],
+    content_key: Option<&ContentKey>,
     secret_keys: &[Keypair],
+    group_keys: &[GroupKey],
     log_seq: &Sequence,
     item: &[u8],
-) -> Result<(), Error> {
+) -> Result<Msg<Value>, Error> {
     let msg: Msg<Value> = serde_json::from_slice(item)?;
 
     let is_encrypted = !msg.value.content.is_object();
-    let (is_decrypted, msg) = attempt_decryption(msg, secret_keys);
+    let dm_recps = select_dm_recps(sql, &msg, secret_keys).await?;
+    let (is_decrypted, mut msg) = attempt_decryption(msg, secret_keys, group_keys, &dm_recps);
+    msg.value.is_private = is_encrypted;
 
     let msg_ref_id = find_or_create_msg_ref(sql, &msg.key).await?;
     insert_msg(sql, &msg, log_seq, msg_ref_id, is_encrypted, is_decrypted).await?;
+    if is_decrypted {
+        insert_decrypted_content(sql, content_key, msg_ref_id, &msg.value.content).await?;
+        let conversation_msg = ConversationMsgRow {
+            msg_ref_id,
+            log_seq: *log_seq,
+            timestamp_received: msg.timestamp_received,
+            author: msg.value.author.clone(),
+            content: msg.value.content.clone(),
+        };
+        insert_private_conversation_msg(sql, &conversation_msg).await?;
+    }
 
     if is_encrypted && !is_decrypted {
         // early return if content is encrypted and not decrypted
         // eprintln!("No content: {:?}", msg.value.content);
-        return Ok(());
+        return Ok(msg);
     }
 
     let content_result: Result<MsgContent, JsonError> = from_value(msg.value.content.clone());
@@ -189,34 +572,261 @@ async fn append_item(
             // eprintln!("Error: {}", error);
             // eprintln!("-> Content: {:?}", msg.value.content);
             // return Err(error.into());
-            return Ok(());
+            return Ok(msg);
         }
     };
 
-    insert_content(sql, &msg, &content, msg_ref_id, is_decrypted).await?;
+    let indexed_msg = IndexedMsg {
+        msg: &msg,
+        content: &content,
+        msg_ref_id,
+        is_decrypted,
+    };
+    index_msg(sql, indexers, indexed_msg).await?;
 
-    Ok(())
+    Ok(msg)
 }
 
-fn attempt_decryption(mut msg: Msg<Value>, secret_keys: &[Keypair]) -> (bool, Msg<Value>) {
-    let mut is_decrypted = false;
+// A box2 direct msg is opened with the key its author shares with a recipient, so a msg we
+// sent needs the key of one of its recipients. `dm_recps` are the feeds to try for those,
+// see `select_dm_recps`.
+pub(crate) fn attempt_decryption(
+    mut msg: Msg<Value>,
+    secret_keys: &[Keypair],
+    group_keys: &[GroupKey],
+    dm_recps: &[FeedRef],
+) -> (bool, Msg<Value>) {
+    let mut decrypted = None;
 
     if let Value::String(ref content) = msg.value.content {
-        let string = content.trim_end_matches(".box");
-
-        let decoded = b64.decode(string);
-        if let Ok(bytes) = decoded {
-            for secret_key in secret_keys {
-                if let Some(decrypted) = private_box::decrypt(&bytes, secret_key) {
-                    is_decrypted = true;
-                    if let Ok(new_content) = serde_json::from_slice(&decrypted) {
-                        msg.value.content = new_content;
-                    }
-                    break;
-                }
+        if let Some(string) = content.strip_suffix(".box2") {
+            if let Ok(bytes) = b64.decode(string) {
+                let author = &msg.value.author;
+                let recp_keys: Vec<RecpKey> = group_keys
+                    .iter()
+                    .map(RecpKey::group)
+                    .chain(secret_keys.iter().flat_map(|secret_key| {
+                        let is_ours = author.as_bytes() == secret_key.public.0;
+                        let recps = if is_ours { dm_recps } else { &[] };
+                        iter::once(author).chain(recps).filter_map(move |feed| {
+                            RecpKey::dm(&secret_key.secret.0[..32], &secret_key.public.0, feed)
+                        })
+                    }))
+                    .collect();
+                decrypted = unbox2(&bytes, author, msg.value.previous.as_ref(), &recp_keys);
+            }
+        } else {
+            let string = content.trim_end_matches(".box");
+
+            let decoded = b64.decode(string);
+            if let Ok(bytes) = decoded {
+                decrypted = secret_keys
+                    .iter()
+                    .find_map(|secret_key| private_box::decrypt(&bytes, secret_key));
             }
         }
     };
 
+    let is_decrypted = decrypted.is_some();
+    if let Some(Ok(new_content)) = decrypted.map(|bytes| serde_json::from_slice(&bytes)) {
+        msg.value.content = new_content;
+    }
+
     (is_decrypted, msg)
 }
+
+// The feeds to try as recipients of a box2 msg authored by one of our keys, which are the
+// feeds the author follows. Empty for other msgs.
+pub(crate) async fn select_dm_recps(
+    sql: &mut SqliteConnection,
+    msg: &Msg<Value>,
+    secret_keys: &[Keypair],
+) -> Result<Vec<FeedRef>, Error> {
+    let author = &msg.value.author;
+    let is_box2 =
+        matches!(&msg.value.content, Value::String(content) if content.ends_with(".box2"));
+    let is_ours = secret_keys
+        .iter()
+        .any(|secret_key| author.as_bytes() == secret_key.public.0);
+    if !is_box2 || !is_ours {
+        return Ok(Vec::new());
+    }
+
+    Ok(select_following(sql, author).await?)
+}
+
+// Try keys on the indexed msgs that no key could decrypt yet and index the ones they
+// decrypt. Decrypted msgs can add us to groups, whose keys are then tried in turn and
+// added to `group_keys`. Returns the number of msgs decrypted.
+pub(crate) async fn decrypt_indexed_msgs(
+    sql: &mut SqliteConnection,
+    log: &OffsetLog<u32>,
+    indexers: &[Arc<dyn Indexer>],
+    content_key: Option<&ContentKey>,
+    secret_keys: &[Keypair],
+    group_keys: &mut Vec<GroupKey>,
+) -> Result<usize, Error> {
+    let mut decrypted_count = 0;
+    let mut secret_keys = secret_keys;
+    let mut new_group_keys = Vec::new();
+    loop {
+        if !secret_keys.is_empty() || !new_group_keys.is_empty() {
+            decrypted_count += decrypt_indexed_msgs_with(
+                sql,
+                log,
+                indexers,
+                content_key,
+                secret_keys,
+                &new_group_keys,
+            )
+            .await?;
+        }
+
+        let known_group_keys = select_group_keys(sql).await?;
+        if known_group_keys.len() == group_keys.len() {
+            break;
+        }
+        new_group_keys = known_group_keys[group_keys.len()..].to_vec();
+        *group_keys = known_group_keys;
+        secret_keys = &[];
+    }
+
+    if decrypted_count > 0 {
+        let mut tx = sql.begin().await?;
+        insert_missing_private_conversation_msgs(&mut tx, content_key).await?;
+        tx.commit().await?;
+    }
+
+    Ok(decrypted_count)
+}
+
+async fn decrypt_indexed_msgs_with(
+    sql: &mut SqliteConnection,
+    log: &OffsetLog<u32>,
+    indexers: &[Arc<dyn Indexer>],
+    content_key: Option<&ContentKey>,
+    secret_keys: &[Keypair],
+    group_keys: &[GroupKey],
+) -> Result<usize, Error> {
+    let mut decrypted_count = 0;
+    let mut after_log_seq = None;
+    loop {
+        let rows = select_encrypted_msgs(sql, false, after_log_seq, 1000).await?;
+        let Some((last_log_seq, _)) = rows.last() else {
+            break;
+        };
+        after_log_seq = Some(*last_log_seq);
+
+        let mut decrypted = Vec::new();
+        for (log_seq, msg_ref_id) in rows {
+            let msg = read_log_msg(log, log_seq)?;
+            let dm_recps = select_dm_recps(sql, &msg, secret_keys).await?;
+            let (is_decrypted, msg) = attempt_decryption(msg, secret_keys, group_keys, &dm_recps);
+            if is_decrypted {
+                decrypted.push((log_seq, msg_ref_id, msg));
+            }
+        }
+        decrypted_count += decrypted.len();
+
+        let mut tx = sql.begin().await?;
+        for (log_seq, msg_ref_id, msg) in decrypted {
+            let content_type = msg.value.content.get("type").and_then(Value::as_str);
+            update_msg_decrypted(&mut tx, log_seq, content_type).await?;
+            let content = &msg.value.content;
+            insert_decrypted_content(&mut tx, content_key, msg_ref_id, content).await?;
+            if let Ok(content) = from_value::<MsgContent>(msg.value.content.clone()) {
+                let indexed_msg = IndexedMsg {
+                    msg: &msg,
+                    content: &content,
+                    msg_ref_id,
+                    is_decrypted: true,
+                };
+                index_msg(&mut tx, indexers, indexed_msg).await?;
+            }
+        }
+        tx.commit().await?;
+    }
+
+    Ok(decrypted_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn publish_indexes_entries_appended_by_other_writers() {
+        let dir = temp_dir();
+        let (alice, bob) = (keypair(1), keypair(2));
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let other = MsgBuilder::new(&post("from another writer"))
+            .unwrap()
+            .sign(&bob);
+        append_to_log(&dir, &other);
+
+        let first = db.publish(&alice, &post("first")).await.unwrap();
+        let second = db.publish(&alice, &post("second")).await.unwrap();
+
+        let reader = db.reader();
+        assert!(reader.get_msg(other.key).await.unwrap().is_some());
+        assert!(reader.get_msg(first.key.clone()).await.unwrap().is_some());
+        assert_eq!(second.value.sequence, 2);
+        assert_eq!(
+            second.value.previous.map(|previous| previous.to_string()),
+            Some(first.key.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn readers_can_read_msgs_as_soon_as_they_are_indexed() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let reader = db.reader();
+
+        for text in ["first", "second", "third"] {
+            let published = db.publish(&alice, &post(text)).await.unwrap();
+            let msgs = reader
+                .get_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+                    feed_ref: &keypair_feed_ref(&alice),
+                    content_type: None,
+                    page_size: 10,
+                    less_than_feed_seq: i64::MAX,
+                    is_decrypted: false,
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                msgs.first().map(|msg| msg.key.to_string()),
+                Some(published.key.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn decrypts_dms_we_sent_to_feeds_we_follow() {
+        let dir = temp_dir();
+        let (alice, me) = (keypair(1), keypair(2));
+        let alice_feed = keypair_feed_ref(&alice);
+        let follow = from_value(json!({
+            "type": "contact",
+            "contact": Into::<String>::into(&alice_feed),
+            "following": true,
+        }))
+        .unwrap();
+        append_to_log(&dir, &MsgBuilder::new(&follow).unwrap().sign(&me));
+        let content = json!({
+            "type": "post",
+            "text": "hi",
+            "recps": [alice_feed, keypair_feed_ref(&me)],
+        });
+        let dm = append_dm(&dir, &me, &alice_feed, content);
+
+        let mut db = open_db(&dir, vec![private_keypair(&me)], Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+        let msg = db.reader().get_msg(dm.key).await.unwrap().unwrap();
+        assert_eq!(msg.value.content["text"], "hi");
+    }
+}
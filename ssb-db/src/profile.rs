@@ -0,0 +1,152 @@
+use serde_json::{from_value, Value};
+use ssb_msg::AboutContent;
+use ssb_ref::{BlobRef, FeedRef};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::sql::AboutFeedName;
+
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub feed_ref: FeedRef,
+    // The latest self-assigned name, or else the name most others gave this feed
+    pub name: Option<String>,
+    // The latest self-assigned image, or else the latest image others gave this feed
+    pub image: Option<BlobRef>,
+    // Only ever self-assigned
+    pub description: Option<String>,
+    // Names others gave this feed, most given first
+    pub names_given_by_others: Vec<GivenName>,
+    // Names this feed gave itself, oldest first
+    pub name_history: Vec<ProfileName>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GivenName {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileName {
+    pub name: String,
+    pub feed_seq: u64,
+    pub timestamp_asserted: f64,
+}
+
+// Resolve a profile from the merged about content each feed published about it.
+//
+// `abouts` are flagged when self-assigned and ordered by when the latest about msg of each
+// author was received, oldest first.
+pub(crate) fn build_profile(
+    feed_ref: &FeedRef,
+    abouts: Vec<(bool, Value)>,
+    names: Vec<AboutFeedName>,
+) -> Profile {
+    let mut own_about = None;
+    let mut others_abouts = Vec::new();
+    for (is_own, content) in abouts {
+        let Some(about) = to_about_content(feed_ref, content) else {
+            continue;
+        };
+        if is_own {
+            own_about = Some(about);
+        } else {
+            others_abouts.push(about);
+        }
+    }
+
+    // count names, remembering which was given last to break ties
+    let mut name_counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (index, name) in others_abouts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, about)| Some((index, about.name.as_deref()?)))
+    {
+        let entry = name_counts.entry(name).or_insert((0, index));
+        entry.0 += 1;
+        entry.1 = index;
+    }
+    let mut name_counts: Vec<(&str, (usize, usize))> = name_counts.into_iter().collect();
+    name_counts.sort_by_key(|(_, key)| Reverse(*key));
+    let names_given_by_others: Vec<GivenName> = name_counts
+        .into_iter()
+        .map(|(name, (count, _))| GivenName {
+            name: name.to_string(),
+            count,
+        })
+        .collect();
+
+    let own_name = own_about.as_ref().and_then(|about| about.name.clone());
+    let own_image = own_about
+        .as_ref()
+        .and_then(|about| about.image.as_ref())
+        .map(|image| image.link.clone());
+    let others_image = others_abouts
+        .iter()
+        .rev()
+        .find_map(|about| about.image.as_ref())
+        .map(|image| image.link.clone());
+
+    Profile {
+        feed_ref: feed_ref.clone(),
+        name: own_name.or_else(|| names_given_by_others.first().map(|name| name.name.clone())),
+        image: own_image.or(others_image),
+        description: own_about.and_then(|about| about.description),
+        names_given_by_others,
+        name_history: names
+            .into_iter()
+            .map(|name| ProfileName {
+                name: name.name,
+                feed_seq: name.feed_seq as u64,
+                timestamp_asserted: name.timestamp_asserted,
+            })
+            .collect(),
+    }
+}
+
+// The stored content has its `type` and `about` keys removed, put `about` back to parse it.
+fn to_about_content(feed_ref: &FeedRef, content: Value) -> Option<AboutContent> {
+    let Value::Object(mut content) = content else {
+        return None;
+    };
+    content.insert("about".to_string(), Value::String(feed_ref.into()));
+    from_value(Value::Object(content)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn feed() -> FeedRef {
+        FeedRef::from_string("@J8jbLTPlPaKOsNzQ/nEDdJ+hHvFSrufJ7I9M2u6nlGs=.ed25519".to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn build_profile_prefers_self_assigned_name() {
+        let subject = feed();
+        let abouts = vec![
+            (false, json!({ "name": "someone" })),
+            (true, json!({ "name": "me", "description": "hi" })),
+        ];
+        let profile = build_profile(&subject, abouts, Vec::new());
+        assert_eq!(profile.name.as_deref(), Some("me"));
+        assert_eq!(profile.description.as_deref(), Some("hi"));
+        assert_eq!(profile.names_given_by_others[0].name, "someone");
+    }
+
+    #[test]
+    fn build_profile_falls_back_to_most_given_name() {
+        let subject = feed();
+        let abouts = vec![
+            (false, json!({ "name": "a" })),
+            (false, json!({ "name": "b" })),
+            (false, json!({ "name": "b" })),
+        ];
+        let profile = build_profile(&subject, abouts, Vec::new());
+        assert_eq!(profile.name.as_deref(), Some("b"));
+        assert_eq!(profile.description, None);
+    }
+}
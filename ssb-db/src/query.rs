@@ -0,0 +1,255 @@
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD as b64url, Engine};
+use flumedb::Sequence;
+use serde_json::Value;
+use ssb_msg::Msg;
+use ssb_ref::{FeedRef, MsgRef};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error as ThisError;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+// A query for the msgs matching all of its filters, a page at a time.
+//
+// Pages are newest first unless `oldest_first` is set. Pass the `end` cursor of a page to
+// `page_after` to get the next page, or its `start` cursor to `page_before` to get the
+// previous one.
+#[derive(Clone, Debug)]
+pub struct MsgQuery {
+    pub(crate) authors: Vec<FeedRef>,
+    pub(crate) content_types: Vec<String>,
+    pub(crate) asserted_since: Option<f64>,
+    pub(crate) asserted_until: Option<f64>,
+    pub(crate) received_since: Option<f64>,
+    pub(crate) received_until: Option<f64>,
+    pub(crate) root: Option<MsgRef>,
+    pub(crate) channel: Option<String>,
+    pub(crate) is_private: Option<bool>,
+    pub(crate) is_undecryptable_included: bool,
+    pub(crate) order: MsgOrder,
+    pub(crate) is_oldest_first: bool,
+    pub(crate) page: Option<QueryPage>,
+    pub(crate) page_size: i64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MsgOrder {
+    // The order msgs were appended to the offset log
+    #[default]
+    Log,
+    // The timestamp the author claims
+    Asserted,
+    Received,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum QueryPage {
+    After(MsgCursor),
+    Before(MsgCursor),
+}
+
+// Position of a msg in a query, only valid for queries with the same order.
+//
+// It is displayed as an opaque url safe string, parse it back with `str::parse`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsgCursor {
+    pub(crate) order: MsgOrder,
+    pub(crate) key: f64,
+    pub(crate) log_seq: Sequence,
+}
+
+#[derive(Debug, ThisError)]
+#[error("Invalid msg cursor")]
+pub struct InvalidCursor;
+
+impl Default for MsgQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MsgQuery {
+    pub fn new() -> Self {
+        Self {
+            authors: Vec::new(),
+            content_types: Vec::new(),
+            asserted_since: None,
+            asserted_until: None,
+            received_since: None,
+            received_until: None,
+            root: None,
+            channel: None,
+            is_private: None,
+            is_undecryptable_included: false,
+            order: MsgOrder::Log,
+            is_oldest_first: false,
+            page: None,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    // Msgs by any of the feeds
+    pub fn authors(mut self, authors: impl IntoIterator<Item = FeedRef>) -> Self {
+        self.authors.extend(authors);
+        self
+    }
+
+    // Msgs with any of the content types
+    pub fn content_types<T: Into<String>>(
+        mut self,
+        content_types: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.content_types
+            .extend(content_types.into_iter().map(Into::into));
+        self
+    }
+
+    // Timestamps are milliseconds since the unix epoch, `since` is inclusive and `until` is
+    // exclusive.
+    pub fn asserted_between(mut self, since: Option<f64>, until: Option<f64>) -> Self {
+        self.asserted_since = since;
+        self.asserted_until = until;
+        self
+    }
+
+    pub fn received_between(mut self, since: Option<f64>, until: Option<f64>) -> Self {
+        self.received_since = since;
+        self.received_until = until;
+        self
+    }
+
+    // Replies in the thread of a root post
+    pub fn root(mut self, root: MsgRef) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    // Posts in a channel, normalised as a hashtag so "#Rust" and "rust" are the same channel
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = Some(channel.into());
+        self
+    }
+
+    // Only decrypted private msgs, or only public msgs. Both are returned by default.
+    pub fn private(mut self, is_private: bool) -> Self {
+        self.is_private = Some(is_private);
+        self
+    }
+
+    // Also return private msgs none of the keys could decrypt, with their encrypted content.
+    // Ignored when only private or only public msgs are asked for.
+    pub fn include_undecryptable(mut self) -> Self {
+        self.is_undecryptable_included = true;
+        self
+    }
+
+    pub fn order_by(mut self, order: MsgOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn oldest_first(mut self) -> Self {
+        self.is_oldest_first = true;
+        self
+    }
+
+    pub fn page_size(mut self, page_size: i64) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn page_after(mut self, cursor: MsgCursor) -> Self {
+        self.page = Some(QueryPage::After(cursor));
+        self
+    }
+
+    pub fn page_before(mut self, cursor: MsgCursor) -> Self {
+        self.page = Some(QueryPage::Before(cursor));
+        self
+    }
+}
+
+impl MsgOrder {
+    fn tag(&self) -> char {
+        match self {
+            MsgOrder::Log => 'l',
+            MsgOrder::Asserted => 'a',
+            MsgOrder::Received => 'r',
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "l" => Some(MsgOrder::Log),
+            "a" => Some(MsgOrder::Asserted),
+            "r" => Some(MsgOrder::Received),
+            _ => None,
+        }
+    }
+}
+
+impl MsgCursor {
+    pub(crate) fn new(order: MsgOrder, log_seq: Sequence, msg: &Msg<Value>) -> Self {
+        let key = match order {
+            MsgOrder::Log => log_seq as f64,
+            MsgOrder::Asserted => msg.value.timestamp_asserted,
+            MsgOrder::Received => msg.timestamp_received,
+        };
+        Self {
+            order,
+            key,
+            log_seq,
+        }
+    }
+}
+
+impl fmt::Display for MsgCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cursor = format!("{}:{}:{}", self.order.tag(), self.key, self.log_seq);
+        f.write_str(&b64url.encode(cursor))
+    }
+}
+
+impl FromStr for MsgCursor {
+    type Err = InvalidCursor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cursor = b64url.decode(s).map_err(|_| InvalidCursor)?;
+        let cursor = String::from_utf8(cursor).map_err(|_| InvalidCursor)?;
+        let mut parts = cursor.split(':');
+        let (Some(order), Some(key), Some(log_seq), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(InvalidCursor);
+        };
+
+        Ok(Self {
+            order: MsgOrder::from_tag(order).ok_or(InvalidCursor)?,
+            key: key.parse().map_err(|_| InvalidCursor)?,
+            log_seq: log_seq.parse().map_err(|_| InvalidCursor)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips_through_its_string() {
+        let cursor = MsgCursor {
+            order: MsgOrder::Received,
+            key: 1_690_000_000_123.5,
+            log_seq: 42,
+        };
+        assert_eq!(cursor.to_string().parse::<MsgCursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        assert!("".parse::<MsgCursor>().is_err());
+        assert!("not a cursor".parse::<MsgCursor>().is_err());
+        assert!(b64url.encode("x:1:2").parse::<MsgCursor>().is_err());
+        assert!(b64url.encode("l:1:2:3").parse::<MsgCursor>().is_err());
+    }
+}
@@ -0,0 +1,873 @@
+use flumedb::{OffsetLog, Sequence};
+use futures::future::BoxFuture;
+use futures::stream::{self, Stream};
+use serde_json::Value;
+use sqlx::{pool::PoolConnection, Sqlite, SqliteConnection, SqlitePool};
+use ssb_msg::Msg;
+use ssb_ref::{FeedRef, MsgRef};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::chain::{to_chain_issues, ChainIssue};
+use crate::profile::{build_profile, Profile};
+use crate::query::{MsgCursor, MsgQuery, QueryPage};
+use crate::sql::*;
+use crate::thread::{build_thread_tree, Thread};
+use crate::views::{build_contact_views, build_post_views, build_profiles, ContactView, PostView};
+use crate::{read_log_msg, Error};
+
+// A cheap to clone handle for querying the database while it is being indexed.
+//
+// Queries run on a pool of read-only connections, which see the db as of the last
+// committed batch, and msgs are read from the offset log shared with the indexer. Lists of
+// msgs that are paged can also be streamed, a page at a time.
+#[derive(Clone)]
+pub struct DatabaseReader {
+    pool: SqlitePool,
+    log: Arc<RwLock<OffsetLog<u32>>>,
+    content_key: Option<ContentKey>,
+}
+
+// The state of a stream of items that are queried a page at a time
+struct Pages<R, T, F> {
+    sql: Option<PoolConnection<Sqlite>>,
+    items: VecDeque<T>,
+    // The last item of the current page and the log seq of its msg
+    last: Option<(Sequence, T)>,
+    next_page: F,
+    to_item: fn(R, Msg<Value>) -> T,
+}
+
+// A row returned by a query, pointing at the msg to read from the offset log
+trait PageRow {
+    fn log_seq(&self) -> Sequence;
+}
+
+impl PageRow for Sequence {
+    fn log_seq(&self) -> Sequence {
+        *self
+    }
+}
+
+impl PageRow for MsgCursor {
+    fn log_seq(&self) -> Sequence {
+        self.log_seq
+    }
+}
+
+impl PageRow for ReceivedCursor {
+    fn log_seq(&self) -> Sequence {
+        self.log_seq
+    }
+}
+
+impl PageRow for MentionRow {
+    fn log_seq(&self) -> Sequence {
+        self.cursor.log_seq
+    }
+}
+
+impl PageRow for SearchMatch {
+    fn log_seq(&self) -> Sequence {
+        self.log_seq
+    }
+}
+
+#[derive(Debug)]
+pub struct MsgPage {
+    pub msgs: Vec<Msg<Value>>,
+    // Cursors of the first and last msg, None if the page is empty
+    pub start: Option<MsgCursor>,
+    pub end: Option<MsgCursor>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub msg: Msg<Value>,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mention {
+    pub kind: MentionKind,
+    pub msg: Msg<Value>,
+    // Pass the cursor of the last mention to get the next page
+    pub cursor: ReceivedCursor,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConversationMsg {
+    pub msg: Msg<Value>,
+    // Pass the cursor of the last msg to get the next page
+    pub cursor: ReceivedCursor,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimelineMsg {
+    pub msg: Msg<Value>,
+    // Pass the cursor of the last msg to get the next page
+    pub cursor: ReceivedCursor,
+}
+
+#[derive(Debug)]
+pub struct VotedMsg {
+    pub msg: Msg<Value>,
+    pub vote_count: i64,
+}
+
+impl DatabaseReader {
+    pub(crate) fn new(
+        pool: SqlitePool,
+        log: Arc<RwLock<OffsetLog<u32>>>,
+        content_key: Option<ContentKey>,
+    ) -> Self {
+        Self {
+            pool,
+            log,
+            content_key,
+        }
+    }
+
+    // A read-only connection, for querying the tables of custom indexers
+    pub async fn acquire(&self) -> Result<PoolConnection<Sqlite>, Error> {
+        Ok(self.pool.acquire().await?)
+    }
+
+    pub async fn get_msg(&self, msg_ref: MsgRef) -> Result<Option<Msg<Value>>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let log_seq_opt = get_msg_log_seq(&mut sql, &msg_ref).await?;
+        if let Some(log_seq) = log_seq_opt {
+            Ok(Some(self.read_msg(&mut sql, log_seq).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn get_all_msgs_by_feed(
+        &self,
+        options: SelectAllMsgsByFeedOptions<'_>,
+    ) -> Result<Vec<Msg<Value>>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let log_seqs = select_all_msg_log_seqs_by_feed(&mut sql, options).await?;
+        self.read_msgs(&mut sql, &log_seqs).await
+    }
+
+    // Get a page of the msgs matching a query.
+    pub async fn query_msgs(&self, msg_query: &MsgQuery) -> Result<MsgPage, Error> {
+        if let Some(QueryPage::After(cursor) | QueryPage::Before(cursor)) = msg_query.page {
+            if cursor.order != msg_query.order {
+                return Err(Error::CursorOrderMismatch {});
+            }
+        }
+
+        let mut sql = self.pool.acquire().await?;
+        let cursors = select_msg_query(&mut sql, msg_query).await?;
+        let log_seqs: Vec<Sequence> = cursors.iter().map(|cursor| cursor.log_seq).collect();
+        let msgs = self.read_msgs(&mut sql, &log_seqs).await?;
+        Ok(MsgPage {
+            msgs,
+            start: cursors.first().copied(),
+            end: cursors.last().copied(),
+        })
+    }
+
+    // Stream the msgs matching a query from its page onwards.
+    pub fn stream_msgs(
+        &self,
+        msg_query: MsgQuery,
+    ) -> impl Stream<Item = Result<Msg<Value>, Error>> + '_ {
+        self.stream_pages(
+            move |mut sql, last| {
+                let msg_query = match last {
+                    Some((log_seq, msg)) => {
+                        msg_query
+                            .clone()
+                            .page_after(MsgCursor::new(msg_query.order, log_seq, msg))
+                    }
+                    None => msg_query.clone(),
+                };
+                Box::pin(async move {
+                    let cursors = select_msg_query(&mut sql, &msg_query).await?;
+                    Ok((sql, cursors))
+                })
+            },
+            |_, msg| msg,
+        )
+    }
+
+    // Stream all msgs of a feed before `less_than_feed_seq`, newest first. Only a page of
+    // `page_size` msgs is held at a time.
+    pub fn stream_all_msgs_by_feed<'a>(
+        &'a self,
+        options: SelectAllMsgsByFeedOptions<'a>,
+    ) -> impl Stream<Item = Result<Msg<Value>, Error>> + 'a {
+        self.stream_pages(
+            move |mut sql, last: Option<(Sequence, &Msg<Value>)>| {
+                let options = SelectAllMsgsByFeedOptions {
+                    less_than_feed_seq: last.map_or(options.less_than_feed_seq, |(_, msg)| {
+                        msg.value.sequence as i64
+                    }),
+                    ..options
+                };
+                let before_log_seq = last.map(|(log_seq, _)| log_seq);
+                Box::pin(async move {
+                    let log_seqs =
+                        select_all_msg_log_seqs_by_feed_before(&mut sql, options, before_log_seq)
+                            .await?;
+                    Ok((sql, log_seqs))
+                })
+            },
+            |_, msg| msg,
+        )
+    }
+
+    // Get msgs tagged with a hashtag or posted in a channel of the same name, newest first.
+    pub async fn get_msgs_by_hashtag(
+        &self,
+        options: SelectMsgsByHashtagOptions<'_>,
+    ) -> Result<Vec<Msg<Value>>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let log_seqs = select_msg_log_seqs_by_hashtag(&mut sql, options).await?;
+        self.read_msgs(&mut sql, &log_seqs).await
+    }
+
+    // Stream all msgs tagged with a hashtag before `less_than_log_seq`, newest first.
+    pub fn stream_msgs_by_hashtag<'a>(
+        &'a self,
+        options: SelectMsgsByHashtagOptions<'a>,
+    ) -> impl Stream<Item = Result<Msg<Value>, Error>> + 'a {
+        self.stream_pages(
+            move |mut sql, last| {
+                let options = SelectMsgsByHashtagOptions {
+                    less_than_log_seq: last
+                        .map(|(log_seq, _)| log_seq)
+                        .or(options.less_than_log_seq),
+                    ..options
+                };
+                Box::pin(async move {
+                    let log_seqs = select_msg_log_seqs_by_hashtag(&mut sql, options).await?;
+                    Ok((sql, log_seqs))
+                })
+            },
+            |_, msg| msg,
+        )
+    }
+
+    pub async fn get_popular_hashtags(&self, limit: i64) -> Result<Vec<HashtagCount>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_popular_hashtags(&mut sql, limit).await?)
+    }
+
+    // Get the root of a thread and its replies as a tree, in causal order.
+    pub async fn get_thread(&self, root: &MsgRef) -> Result<Option<Thread>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let Some(root_msg_ref_id) = find_msg_ref(&mut sql, root).await? else {
+            return Ok(None);
+        };
+
+        let root_msg = match get_msg_log_seq(&mut sql, root).await? {
+            Some(log_seq) => Some(self.read_msg(&mut sql, log_seq).await?),
+            None => None,
+        };
+
+        let posts = select_thread_posts(&mut sql, root_msg_ref_id).await?;
+        if root_msg.is_none() && posts.is_empty() {
+            return Ok(None);
+        }
+        let log_seqs: Vec<Sequence> = posts.iter().map(|post| post.log_seq).collect();
+        let msgs = self.read_msgs(&mut sql, &log_seqs).await?;
+        let posts_with_msgs = posts.into_iter().zip(msgs).collect();
+
+        let fork_log_seqs = select_thread_fork_log_seqs(&mut sql, root_msg_ref_id).await?;
+        let forks = self.read_msgs(&mut sql, &fork_log_seqs).await?;
+
+        Ok(Some(Thread {
+            root: root_msg,
+            replies: build_thread_tree(posts_with_msgs),
+            forks,
+        }))
+    }
+
+    // Threads with replies, most recently active first. Pass the cursor of the last thread as
+    // `before` to get the next page.
+    pub async fn get_active_threads(
+        &self,
+        before: Option<ThreadCursor>,
+        page_size: i64,
+    ) -> Result<Vec<ThreadSummary>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_active_threads(&mut sql, before, page_size).await?)
+    }
+
+    // Threads the feed started or replied to, most recently active first.
+    pub async fn get_threads_by_participant(
+        &self,
+        feed_ref: &FeedRef,
+        before: Option<ThreadCursor>,
+        page_size: i64,
+    ) -> Result<Vec<ThreadSummary>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_threads_by_participant(&mut sql, feed_ref, before, page_size).await?)
+    }
+
+    // Get the current name, image and description of a feed, with the names others gave it.
+    pub async fn get_profile(&self, feed_ref: &FeedRef) -> Result<Option<Profile>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let abouts = select_about_feeds_by_subject(&mut sql, feed_ref).await?;
+        if abouts.is_empty() {
+            return Ok(None);
+        }
+        let names = select_about_feed_names(&mut sql, feed_ref).await?;
+
+        Ok(Some(build_profile(feed_ref, abouts, names)))
+    }
+
+    pub async fn search(&self, options: SearchOptions<'_>) -> Result<Vec<SearchResult>, Error> {
+        if options.query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut sql = self.pool.acquire().await?;
+        let matches = select_search_matches(&mut sql, options).await?;
+        self.read_rows(&mut sql, matches, to_search_result).await
+    }
+
+    // Stream the search results from `offset` onwards, best match first.
+    pub fn stream_search<'a>(
+        &'a self,
+        options: SearchOptions<'a>,
+    ) -> impl Stream<Item = Result<SearchResult, Error>> + 'a {
+        let mut offset = options.offset;
+        self.stream_pages(
+            move |mut sql, _| {
+                let options = SearchOptions { offset, ..options };
+                offset += options.page_size;
+                Box::pin(async move {
+                    if options.query.trim().is_empty() {
+                        return Ok((sql, Vec::new()));
+                    }
+                    let matches = select_search_matches(&mut sql, options).await?;
+                    Ok((sql, matches))
+                })
+            },
+            to_search_result,
+        )
+    }
+
+    // The posts among the msgs, with their author's profile, vote count and reply count.
+    pub async fn get_post_views(&self, msgs: Vec<Msg<Value>>) -> Result<Vec<PostView>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let authors: Vec<FeedRef> = msgs.iter().map(|msg| msg.value.author.clone()).collect();
+        let msg_refs: Vec<MsgRef> = msgs.iter().map(|msg| msg.key.clone()).collect();
+
+        let profiles = select_profiles(&mut sql, authors).await?;
+        let vote_counts: HashMap<String, i64> = select_vote_counts(&mut sql, &msg_refs)
+            .await?
+            .into_iter()
+            .collect();
+        let reply_counts: HashMap<String, i64> = select_reply_counts(&mut sql, &msg_refs)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(build_post_views(
+            msgs,
+            &profiles,
+            &vote_counts,
+            &reply_counts,
+        ))
+    }
+
+    // The contact msgs among the msgs, with the profiles of both feeds.
+    pub async fn get_contact_views(
+        &self,
+        msgs: Vec<Msg<Value>>,
+    ) -> Result<Vec<ContactView>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let contacts = msgs
+            .iter()
+            .filter_map(|msg| msg.value.content.get("contact")?.as_str())
+            .filter_map(|contact| FeedRef::from_string(contact.to_string()).ok());
+        let feed_refs: Vec<FeedRef> = msgs
+            .iter()
+            .map(|msg| msg.value.author.clone())
+            .chain(contacts)
+            .collect();
+
+        let profiles = select_profiles(&mut sql, feed_refs).await?;
+
+        Ok(build_contact_views(msgs, &profiles))
+    }
+
+    pub async fn get_following(&self, feed_ref: &FeedRef) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_following(&mut sql, feed_ref).await?)
+    }
+
+    pub async fn get_followers(&self, feed_ref: &FeedRef) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_followers(&mut sql, feed_ref).await?)
+    }
+
+    pub async fn get_friends(&self, feed_ref: &FeedRef) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_friends(&mut sql, feed_ref).await?)
+    }
+
+    pub async fn get_blocking(&self, feed_ref: &FeedRef) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_blocking(&mut sql, feed_ref).await?)
+    }
+
+    pub async fn get_blocked_by(&self, feed_ref: &FeedRef) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_blocked_by(&mut sql, feed_ref).await?)
+    }
+
+    // All feeds within max_hops follows of a feed, closest first.
+    pub async fn get_hops(
+        &self,
+        feed_ref: &FeedRef,
+        max_hops: u32,
+    ) -> Result<Vec<FeedHops>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_hops(&mut sql, feed_ref, None, max_hops).await?)
+    }
+
+    // The number of follows from one feed to another, if it is at most max_hops.
+    pub async fn get_hop_distance(
+        &self,
+        from_feed_ref: &FeedRef,
+        to_feed_ref: &FeedRef,
+        max_hops: u32,
+    ) -> Result<Option<u32>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let hops = select_hops(&mut sql, from_feed_ref, Some(to_feed_ref), max_hops).await?;
+        Ok(hops.first().map(|feed_hops| feed_hops.hops))
+    }
+
+    // Mentions of a feed, replies in its threads and votes on its msgs by other feeds,
+    // newest received first.
+    pub async fn get_mentions(
+        &self,
+        feed_ref: &FeedRef,
+        before: Option<ReceivedCursor>,
+        page_size: i64,
+    ) -> Result<Vec<Mention>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let rows = select_mentions(&mut sql, feed_ref, before, page_size).await?;
+        self.read_rows(&mut sql, rows, to_mention).await
+    }
+
+    // Stream the mentions of a feed received before the cursor, newest received first.
+    pub fn stream_mentions<'a>(
+        &'a self,
+        feed_ref: &'a FeedRef,
+        before: Option<ReceivedCursor>,
+        page_size: i64,
+    ) -> impl Stream<Item = Result<Mention, Error>> + 'a {
+        self.stream_pages(
+            move |mut sql, last: Option<(Sequence, &Mention)>| {
+                let before = last.map(|(_, mention)| mention.cursor).or(before);
+                Box::pin(async move {
+                    let rows = select_mentions(&mut sql, feed_ref, before, page_size).await?;
+                    Ok((sql, rows))
+                })
+            },
+            to_mention,
+        )
+    }
+
+    // Posts by the feed and the feeds it follows within `max_hops`, newest received first.
+    pub async fn get_timeline(
+        &self,
+        feed_ref: &FeedRef,
+        options: TimelineOptions,
+    ) -> Result<Vec<TimelineMsg>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let cursors = select_timeline(&mut sql, feed_ref, options).await?;
+        self.read_rows(&mut sql, cursors, |cursor, msg| TimelineMsg { msg, cursor })
+            .await
+    }
+
+    // Stream the timeline of a feed from `options.before` onwards, newest received first.
+    pub fn stream_timeline<'a>(
+        &'a self,
+        feed_ref: &'a FeedRef,
+        options: TimelineOptions,
+    ) -> impl Stream<Item = Result<TimelineMsg, Error>> + 'a {
+        self.stream_pages(
+            move |mut sql, last: Option<(Sequence, &TimelineMsg)>| {
+                let options = TimelineOptions {
+                    before: last.map(|(_, msg)| msg.cursor).or(options.before),
+                    ..options
+                };
+                Box::pin(async move {
+                    let cursors = select_timeline(&mut sql, feed_ref, options).await?;
+                    Ok((sql, cursors))
+                })
+            },
+            |cursor, msg| TimelineMsg { msg, cursor },
+        )
+    }
+
+    pub async fn get_vote_count(&self, msg_ref: &MsgRef) -> Result<i64, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_vote_count(&mut sql, msg_ref).await?)
+    }
+
+    // The feeds currently voting on a msg, in the order they first voted.
+    pub async fn get_votes(&self, msg_ref: &MsgRef) -> Result<Vec<MsgVote>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_votes_by_msg(&mut sql, msg_ref).await?)
+    }
+
+    // Vote counts on a msg per expression, e.g. "Like" or "❤️", most used first.
+    pub async fn get_vote_expressions(
+        &self,
+        msg_ref: &MsgRef,
+    ) -> Result<Vec<ExpressionCount>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_vote_expression_counts(&mut sql, msg_ref).await?)
+    }
+
+    pub async fn get_most_voted_posts(
+        &self,
+        feed_ref: &FeedRef,
+        limit: i64,
+    ) -> Result<Vec<VotedMsg>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let posts = select_most_voted_posts_by_feed(&mut sql, feed_ref, limit).await?;
+        let log_seqs: Vec<Sequence> = posts.iter().map(|post| post.log_seq).collect();
+        let msgs = self.read_msgs(&mut sql, &log_seqs).await?;
+        Ok(posts
+            .into_iter()
+            .zip(msgs)
+            .map(|(post, msg)| VotedMsg {
+                msg,
+                vote_count: post.vote_count,
+            })
+            .collect())
+    }
+
+    // The issues found the last time the feed was validated.
+    pub async fn get_chain_issues(&self, feed_ref: &FeedRef) -> Result<Vec<ChainIssue>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let rows = select_chain_issues_by_feed(&mut sql, feed_ref).await?;
+        Ok(to_chain_issues(rows))
+    }
+
+    pub async fn get_forked_feeds(&self) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_forked_feeds(&mut sql).await?)
+    }
+
+    // Private conversations with activity before the cursor, most recently active first.
+    pub async fn get_private_conversations(
+        &self,
+        before: Option<ReceivedCursor>,
+        page_size: i64,
+    ) -> Result<Vec<PrivateConversation>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_private_conversations(&mut sql, before, page_size).await?)
+    }
+
+    // Msgs of a private conversation received before the cursor, newest first.
+    pub async fn get_private_conversation_msgs(
+        &self,
+        conversation_id: i64,
+        before: Option<ReceivedCursor>,
+        page_size: i64,
+    ) -> Result<Vec<ConversationMsg>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        let cursors =
+            select_private_conversation_msgs(&mut sql, conversation_id, before, page_size).await?;
+        self.read_rows(&mut sql, cursors, |cursor, msg| ConversationMsg {
+            msg,
+            cursor,
+        })
+        .await
+    }
+
+    // Stream the msgs of a private conversation received before the cursor, newest first.
+    pub fn stream_private_conversation_msgs(
+        &self,
+        conversation_id: i64,
+        before: Option<ReceivedCursor>,
+        page_size: i64,
+    ) -> impl Stream<Item = Result<ConversationMsg, Error>> + '_ {
+        self.stream_pages(
+            move |mut sql, last: Option<(Sequence, &ConversationMsg)>| {
+                let before = last.map(|(_, msg)| msg.cursor).or(before);
+                Box::pin(async move {
+                    let cursors = select_private_conversation_msgs(
+                        &mut sql,
+                        conversation_id,
+                        before,
+                        page_size,
+                    )
+                    .await?;
+                    Ok((sql, cursors))
+                })
+            },
+            |cursor, msg| ConversationMsg { msg, cursor },
+        )
+    }
+
+    // Private groups we were added to, in the order we learned of them.
+    pub async fn get_groups(&self) -> Result<Vec<Group>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_groups(&mut sql).await?)
+    }
+
+    pub async fn get_group_members(&self, group_ref: &str) -> Result<Vec<FeedRef>, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_group_members(&mut sql, group_ref).await?)
+    }
+
+    pub async fn get_max_seq_by_feed(&self, feed_ref: &FeedRef) -> Result<i64, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_max_seq_by_feed(&mut sql, feed_ref).await?)
+    }
+
+    pub async fn get_stats(&self) -> Result<Stats, Error> {
+        let mut sql = self.pool.acquire().await?;
+        Ok(select_stats(&mut sql).await?)
+    }
+
+    // Stream the items of each page of rows returned by `next_page`, which is given the
+    // connection to query with and the last item of the previous page. The stream ends at
+    // the first empty page or error.
+    fn stream_pages<'a, R, T, F>(
+        &'a self,
+        next_page: F,
+        to_item: fn(R, Msg<Value>) -> T,
+    ) -> impl Stream<Item = Result<T, Error>> + 'a
+    where
+        R: PageRow + 'a,
+        T: Clone + 'a,
+        F: FnMut(
+                PoolConnection<Sqlite>,
+                Option<(Sequence, &T)>,
+            ) -> BoxFuture<'a, Result<(PoolConnection<Sqlite>, Vec<R>), Error>>
+            + 'a,
+    {
+        let pages = Pages {
+            sql: None,
+            items: VecDeque::new(),
+            last: None,
+            next_page,
+            to_item,
+        };
+        stream::try_unfold(pages, move |mut pages| async move {
+            if pages.items.is_empty() {
+                let sql = match pages.sql.take() {
+                    Some(sql) => sql,
+                    None => self.pool.acquire().await?,
+                };
+                let last = pages.last.as_ref().map(|(log_seq, item)| (*log_seq, item));
+                let (mut sql, rows) = (pages.next_page)(sql, last).await?;
+                let last_log_seq = rows.last().map(PageRow::log_seq);
+
+                pages.items = self.read_rows(&mut sql, rows, pages.to_item).await?.into();
+                pages.sql = Some(sql);
+                if let (Some(log_seq), Some(item)) = (last_log_seq, pages.items.back()) {
+                    pages.last = Some((log_seq, item.clone()));
+                }
+            }
+
+            Ok(pages.items.pop_front().map(|item| (item, pages)))
+        })
+    }
+
+    // Read the msg of each row from the offset log and pair them up.
+    async fn read_rows<R: PageRow, T>(
+        &self,
+        sql: &mut SqliteConnection,
+        rows: Vec<R>,
+        to_item: fn(R, Msg<Value>) -> T,
+    ) -> Result<Vec<T>, Error> {
+        let log_seqs: Vec<Sequence> = rows.iter().map(PageRow::log_seq).collect();
+        let msgs = self.read_msgs(sql, &log_seqs).await?;
+        Ok(rows
+            .into_iter()
+            .zip(msgs)
+            .map(|(row, msg)| to_item(row, msg))
+            .collect())
+    }
+
+    async fn read_msg(
+        &self,
+        sql: &mut SqliteConnection,
+        log_seq: Sequence,
+    ) -> Result<Msg<Value>, Error> {
+        let mut msgs = self.read_msgs(sql, &[log_seq]).await?;
+        Ok(msgs.remove(0))
+    }
+
+    // Read msgs from the offset log, with the content of private msgs decrypted if one of
+    // the keys could decrypt it when it was indexed.
+    async fn read_msgs(
+        &self,
+        sql: &mut SqliteConnection,
+        log_seqs: &[Sequence],
+    ) -> Result<Vec<Msg<Value>>, Error> {
+        let mut msgs = Vec::with_capacity(log_seqs.len());
+        {
+            let log = self.log.read().await;
+            for log_seq in log_seqs {
+                msgs.push(read_log_msg(&log, *log_seq)?);
+            }
+        }
+
+        let private_msg_refs: Vec<MsgRef> = msgs
+            .iter()
+            .filter(|msg| msg.value.content.is_string())
+            .map(|msg| msg.key.clone())
+            .collect();
+        let mut contents =
+            select_decrypted_contents(sql, self.content_key.as_ref(), &private_msg_refs).await?;
+        for msg in msgs.iter_mut() {
+            if msg.value.content.is_string() {
+                msg.value.is_private = true;
+                if let Some(content) = contents.remove(&msg.key.to_string()) {
+                    msg.value.content = content;
+                }
+            }
+        }
+        Ok(msgs)
+    }
+}
+
+// Profiles of many feeds, from a single query of their abouts.
+async fn select_profiles(
+    sql: &mut SqliteConnection,
+    mut feed_refs: Vec<FeedRef>,
+) -> Result<HashMap<String, Profile>, Error> {
+    feed_refs.sort_by_key(|feed_ref| feed_ref.to_string());
+    feed_refs.dedup_by_key(|feed_ref| feed_ref.to_string());
+    let abouts = select_about_feeds_by_subjects(sql, &feed_refs).await?;
+    Ok(build_profiles(&feed_refs, abouts))
+}
+
+fn to_search_result(search_match: SearchMatch, msg: Msg<Value>) -> SearchResult {
+    SearchResult {
+        msg,
+        snippet: search_match.snippet,
+        rank: search_match.rank,
+    }
+}
+
+fn to_mention(row: MentionRow, msg: Msg<Value>) -> Mention {
+    Mention {
+        kind: row.kind,
+        msg,
+        cursor: row.cursor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use ssb_msg::{keypair_feed_ref, MsgBuilder};
+
+    use super::*;
+    use crate::test_utils::*;
+
+    fn texts<'a>(msgs: impl IntoIterator<Item = &'a Msg<Value>>) -> Vec<String> {
+        msgs.into_iter()
+            .map(|msg| msg.value.content["text"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn streams_forked_msgs_of_a_feed() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let alice_feed = keypair_feed_ref(&alice);
+        let first = MsgBuilder::new(&post("first")).unwrap().sign(&alice);
+        append_to_log(&dir, &first);
+        for text in ["fork one", "fork two"] {
+            let fork = MsgBuilder::new(&post(text))
+                .unwrap()
+                .previous(first.key.clone(), 1)
+                .sign(&alice);
+            append_to_log(&dir, &fork);
+        }
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+
+        let msgs: Vec<Msg<Value>> = db
+            .reader()
+            .stream_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+                feed_ref: &alice_feed,
+                content_type: None,
+                page_size: 1,
+                less_than_feed_seq: i64::MAX,
+                is_decrypted: false,
+            })
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(texts(&msgs), ["fork two", "fork one", "first"]);
+    }
+
+    #[tokio::test]
+    async fn streams_every_page() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let alice_feed = keypair_feed_ref(&alice);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        for text in ["post one", "post two", "post three"] {
+            db.publish(&alice, &post(text)).await.unwrap();
+        }
+        let reader = db.reader();
+        let newest_first = ["post three", "post two", "post one"];
+
+        let msgs: Vec<Msg<Value>> = reader
+            .stream_all_msgs_by_feed(SelectAllMsgsByFeedOptions {
+                feed_ref: &alice_feed,
+                content_type: None,
+                page_size: 2,
+                less_than_feed_seq: i64::MAX,
+                is_decrypted: false,
+            })
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(texts(&msgs), newest_first);
+
+        let timeline: Vec<TimelineMsg> = reader
+            .stream_timeline(
+                &alice_feed,
+                TimelineOptions {
+                    max_hops: 1,
+                    include_replies: true,
+                    before: None,
+                    page_size: 1,
+                },
+            )
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(texts(timeline.iter().map(|item| &item.msg)), newest_first);
+
+        let results: Vec<SearchResult> = reader
+            .stream_search(SearchOptions {
+                query: "post",
+                author: Some(&alice_feed),
+                channel: None,
+                since: None,
+                until: None,
+                page_size: 1,
+                offset: 0,
+                include_private: false,
+            })
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+    }
+}
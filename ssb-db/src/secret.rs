@@ -0,0 +1,113 @@
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use private_box::Keypair;
+use serde_derive::Deserialize;
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+pub enum SecretError {
+    #[error("Failed to read secret file {0}: {1}")]
+    ReadFile(PathBuf, #[source] io::Error),
+    #[error("Failed to parse secret file {0}: {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("Secret file {0} uses unsupported curve {1}, only ed25519 is supported")]
+    UnsupportedCurve(PathBuf, String),
+    #[error("Secret file {0} has a private key that is not a base64 encoded ed25519 key")]
+    BadPrivateKey(PathBuf),
+    #[error("Secret file {0} has a private key that does not match its public key")]
+    KeyMismatch(PathBuf),
+}
+
+// The json in a secret file, as written by ssb-keys
+#[derive(Debug, Deserialize)]
+struct SecretFile {
+    curve: String,
+    public: String,
+    private: String,
+}
+
+// Load a keypair from a secret file like ~/.ssb/secret.
+pub fn load_secret_file<P: AsRef<Path>>(path: P) -> Result<Keypair, SecretError> {
+    let path = path.as_ref();
+    let string = read_to_string(path).map_err(|err| SecretError::ReadFile(path.into(), err))?;
+    parse_secret(path, &string)
+}
+
+fn parse_secret(path: &Path, string: &str) -> Result<Keypair, SecretError> {
+    // the json is surrounded by comment lines warning not to share it
+    let json = string
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let secret: SecretFile =
+        serde_json::from_str(&json).map_err(|err| SecretError::Parse(path.into(), err))?;
+
+    if secret.curve != "ed25519" {
+        return Err(SecretError::UnsupportedCurve(path.into(), secret.curve));
+    }
+
+    let private = decode_key(&secret.private)
+        .filter(|private| private.len() == 64)
+        .ok_or_else(|| SecretError::BadPrivateKey(path.into()))?;
+    // an ed25519 private key ends with its public key
+    if decode_key(&secret.public).as_deref() != Some(&private[32..]) {
+        return Err(SecretError::KeyMismatch(path.into()));
+    }
+
+    Keypair::from_slice(&private).ok_or_else(|| SecretError::BadPrivateKey(path.into()))
+}
+
+fn decode_key(key: &str) -> Option<Vec<u8>> {
+    let data = key.strip_suffix(".ed25519").unwrap_or(key);
+    b64.decode(data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::keypair;
+    use ssb_msg::keypair_feed_ref;
+
+    const PUBLIC: &str = "J8jbLTPlPaKOsNzQ/nEDdJ+hHvFSrufJ7I9M2u6nlGs=.ed25519";
+
+    fn secret(curve: &str, private: &str) -> String {
+        secret_with_public(curve, PUBLIC, private)
+    }
+
+    fn secret_with_public(curve: &str, public: &str, private: &str) -> String {
+        format!(
+            "# this is your SECRET name.\n{{\n  \"curve\": \"{curve}\",\n  \"public\": \"{public}\",\n  \"private\": \"{private}\",\n  \"id\": \"@{public}\"\n}}\n# WARNING! It's vital that you DO NOT edit OR share your secret name\n"
+        )
+    }
+
+    #[test]
+    fn parse_secret_reads_keypair_between_comments() {
+        let alice = keypair(1);
+        let public = format!("{}.ed25519", b64.encode(alice.public.as_bytes()));
+        let private = format!("{}.ed25519", b64.encode(alice.to_bytes()));
+        let file = secret_with_public("ed25519", &public, &private);
+
+        let parsed = parse_secret(Path::new("secret"), &file).unwrap();
+        assert_eq!(parsed.public.0, *alice.public.as_bytes());
+        assert_eq!(
+            format!("@{}.ed25519", b64.encode(parsed.public.0)),
+            Into::<String>::into(keypair_feed_ref(&alice))
+        );
+    }
+
+    #[test]
+    fn parse_secret_rejects_other_curves() {
+        let result = parse_secret(Path::new("secret"), &secret("k256", "x.k256"));
+        assert!(matches!(result, Err(SecretError::UnsupportedCurve(_, curve)) if curve == "k256"));
+    }
+
+    #[test]
+    fn parse_secret_rejects_mismatched_keys() {
+        let private = b64.encode([7u8; 64]);
+        let result = parse_secret(Path::new("secret"), &secret("ed25519", &private));
+        assert!(matches!(result, Err(SecretError::KeyMismatch(_))));
+    }
+}
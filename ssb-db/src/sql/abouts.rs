@@ -1,8 +1,8 @@
 use log::trace;
 use serde_json::Value;
-use sqlx::{query, Error, SqliteConnection};
+use sqlx::{query, sqlite::SqliteRow, Error, QueryBuilder, Sqlite, SqliteConnection};
 use ssb_msg::{AboutContent, Msg};
-use ssb_ref::LinkRef;
+use ssb_ref::{FeedRef, LinkRef};
 
 use crate::sql::*;
 
@@ -14,6 +14,7 @@ pub async fn create_abouts_tables(connection: &mut SqliteConnection) -> Result<(
         CREATE TABLE IF NOT EXISTS about_feeds (
             id INTEGER PRIMARY KEY,
             feed_seq INTEGER NOT NULL,
+            timestamp_received REAL NOT NULL,
             link_from_feed_ref_id INTEGER NOT NULL,
             link_to_feed_ref_id INTEGER NOT NULL,
             content JSON NOT NULL,
@@ -53,6 +54,47 @@ pub async fn create_abouts_tables(connection: &mut SqliteConnection) -> Result<(
     .execute(&mut *connection)
     .await?;
 
+    create_about_feed_names_tables(connection).await?;
+
+    Ok(())
+}
+
+pub async fn create_about_feed_names_tables(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Creating about_feed_names tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS about_feed_names (
+            id INTEGER PRIMARY KEY,
+            feed_seq INTEGER NOT NULL,
+            feed_ref_id INTEGER NOT NULL,
+            timestamp_asserted REAL NOT NULL,
+            name TEXT NOT NULL,
+            FOREIGN KEY (feed_ref_id)
+                REFERENCES feed_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// When the latest about merged into each row was received, to order abouts by author
+pub async fn add_about_feeds_timestamp_received_column(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Adding about_feeds timestamp_received column");
+
+    query("ALTER TABLE about_feeds ADD COLUMN timestamp_received REAL NOT NULL DEFAULT 0")
+        .execute(connection)
+        .await?;
+
     Ok(())
 }
 
@@ -72,6 +114,12 @@ pub async fn insert_abouts(
         LinkRef::Feed(feed_ref) => {
             let link_to_feed_ref_id = find_or_create_feed_ref(&mut *connection, feed_ref).await?;
 
+            if let (true, Some(name)) =
+                (link_from_feed_ref_id == link_to_feed_ref_id, &content.name)
+            {
+                insert_about_feed_name(&mut *connection, msg, link_from_feed_ref_id, name).await?;
+            }
+
             let row: Option<(i64, i64, Value)> =
                 query("SELECT id, feed_seq, content FROM about_feeds WHERE link_from_feed_ref_id = ? AND link_to_feed_ref_id = ?")
                     .bind(&link_from_feed_ref_id)
@@ -83,10 +131,16 @@ pub async fn insert_abouts(
             if let Some((id, feed_seq, db_content)) = row {
                 if feed_seq < msg.value.sequence as i64 {
                     for (key, value) in db_content.as_object().unwrap().iter() {
-                        json_content.insert(key.clone(), value.clone());
+                        json_content
+                            .entry(key.clone())
+                            .or_insert_with(|| value.clone());
                     }
-                    query("UPDATE about_feeds SET content = ? WHERE id = ?")
-                        .bind(Value::Object(json_content))
+                    query(
+                        "UPDATE about_feeds SET feed_seq = ?, timestamp_received = ?, content = ? WHERE id = ?",
+                    )
+                    .bind(msg.value.sequence as i64)
+                    .bind(msg.timestamp_received)
+                    .bind(Value::Object(json_content))
                         .bind(id)
                         .execute(connection)
                         .await?;
@@ -96,13 +150,15 @@ pub async fn insert_abouts(
                     "
                     INSERT INTO about_feeds (
                         feed_seq,
+                        timestamp_received,
                         link_from_feed_ref_id,
                         link_to_feed_ref_id,
                         content
-                    ) VALUES (?, ?, ?, ?)
+                    ) VALUES (?, ?, ?, ?, ?)
                     ",
                 )
                 .bind(msg.value.sequence as i64)
+                .bind(msg.timestamp_received)
                 .bind(&link_from_feed_ref_id)
                 .bind(&link_to_feed_ref_id)
                 .bind(Value::Object(json_content))
@@ -124,9 +180,12 @@ pub async fn insert_abouts(
             if let Some((id, feed_seq, db_content)) = row {
                 if feed_seq < msg.value.sequence as i64 {
                     for (key, value) in db_content.as_object().unwrap().iter() {
-                        json_content.insert(key.clone(), value.clone());
+                        json_content
+                            .entry(key.clone())
+                            .or_insert_with(|| value.clone());
                     }
-                    query("UPDATE about_feeds SET content = ? WHERE id = ?")
+                    query("UPDATE about_msgs SET feed_seq = ?, content = ? WHERE id = ?")
+                        .bind(msg.value.sequence as i64)
                         .bind(Value::Object(json_content))
                         .bind(id)
                         .execute(connection)
@@ -157,6 +216,124 @@ pub async fn insert_abouts(
     Ok(())
 }
 
+async fn insert_about_feed_name(
+    connection: &mut SqliteConnection,
+    msg: &Msg<Value>,
+    feed_ref_id: i64,
+    name: &str,
+) -> Result<(), Error> {
+    query(
+        "
+        INSERT INTO about_feed_names (
+            feed_seq,
+            feed_ref_id,
+            timestamp_asserted,
+            name
+        ) VALUES (?, ?, ?, ?)
+        ",
+    )
+    .bind(msg.value.sequence as i64)
+    .bind(feed_ref_id)
+    .bind(msg.value.timestamp_asserted)
+    .bind(name)
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// The merged about content each feed has published about this feed, flagged when it is
+// the feed's own, in the order the latest about of each feed was received
+pub async fn select_about_feeds_by_subject(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<(bool, Value)>, Error> {
+    query(
+        "
+        SELECT
+          about_feeds.link_from_feed_ref_id = about_feeds.link_to_feed_ref_id,
+          about_feeds.content
+        FROM about_feeds
+        JOIN feed_refs ON feed_refs.id = about_feeds.link_to_feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+        ORDER BY about_feeds.timestamp_received, about_feeds.id
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .map(|row: SqliteRow| (row.get(0), row.get(1)))
+    .fetch_all(connection)
+    .await
+}
+
+// The same as `select_about_feeds_by_subject` for many feeds at once, with the subject of
+// each about.
+pub async fn select_about_feeds_by_subjects(
+    connection: &mut SqliteConnection,
+    feed_refs: &[FeedRef],
+) -> Result<Vec<(String, bool, Value)>, Error> {
+    if feed_refs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "
+        SELECT
+          feed_refs.feed_ref,
+          about_feeds.link_from_feed_ref_id = about_feeds.link_to_feed_ref_id,
+          about_feeds.content
+        FROM about_feeds
+        JOIN feed_refs ON feed_refs.id = about_feeds.link_to_feed_ref_id
+        WHERE
+            feed_refs.feed_ref IN (",
+    );
+    let mut subjects = builder.separated(", ");
+    for feed_ref in feed_refs {
+        subjects.push_bind(Into::<String>::into(feed_ref));
+    }
+    builder.push(") ORDER BY about_feeds.timestamp_received, about_feeds.id");
+
+    builder
+        .build()
+        .map(|row: SqliteRow| (row.get(0), row.get(1), row.get(2)))
+        .fetch_all(connection)
+        .await
+}
+
+pub struct AboutFeedName {
+    pub name: String,
+    pub feed_seq: i64,
+    pub timestamp_asserted: f64,
+}
+
+// Names a feed has given itself, oldest first
+pub async fn select_about_feed_names(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<AboutFeedName>, Error> {
+    query(
+        "
+        SELECT
+          about_feed_names.name,
+          about_feed_names.feed_seq,
+          about_feed_names.timestamp_asserted
+        FROM about_feed_names
+        JOIN feed_refs ON feed_refs.id = about_feed_names.feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+        ORDER BY about_feed_names.feed_seq
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .map(|row: SqliteRow| AboutFeedName {
+        name: row.get(0),
+        feed_seq: row.get(1),
+        timestamp_asserted: row.get(2),
+    })
+    .fetch_all(connection)
+    .await
+}
+
 pub async fn create_abouts_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
     trace!("Creating abouts index");
 
@@ -186,5 +363,60 @@ pub async fn create_abouts_indices(connection: &mut SqliteConnection) -> Result<
     .execute(&mut *connection)
     .await?;
 
+    create_about_feed_names_indices(connection).await?;
+
+    Ok(())
+}
+
+pub async fn create_about_feed_names_indices(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Creating about_feed_names index");
+
+    query(
+        "CREATE INDEX IF NOT EXISTS about_feed_names_feed_ref_id_index on about_feed_names (feed_ref_id, feed_seq)",
+    )
+    .execute(connection)
+    .await?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use ssb_msg::{keypair_feed_ref, MsgContent};
+
+    use super::*;
+    use crate::test_utils::*;
+
+    fn about_name(about: &FeedRef, name: &str) -> MsgContent {
+        from_value(json!({ "type": "about", "about": about, "name": name })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn orders_abouts_by_when_they_were_received() {
+        let dir = temp_dir();
+        let (alice, bob, carol) = (keypair(1), keypair(2), keypair(3));
+        let alice_feed = keypair_feed_ref(&alice);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        // bob's about has a higher feed_seq than carol's but was received first
+        db.publish(&bob, &post("one")).await.unwrap();
+        db.publish(&bob, &post("two")).await.unwrap();
+        db.publish(&bob, &about_name(&alice_feed, "bob's alice"))
+            .await
+            .unwrap();
+        db.publish(&carol, &about_name(&alice_feed, "carol's alice"))
+            .await
+            .unwrap();
+
+        let mut connection = db.reader().acquire().await.unwrap();
+        let names: Vec<Value> = select_about_feeds_by_subject(&mut connection, &alice_feed)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, content)| content["name"].clone())
+            .collect();
+        assert_eq!(names, [json!("bob's alice"), json!("carol's alice")]);
+    }
+}
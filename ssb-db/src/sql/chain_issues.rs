@@ -0,0 +1,206 @@
+use log::trace;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::{FeedRef, MsgRef};
+
+use crate::sql::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainIssueKind {
+    Gap,
+    BrokenLink,
+    Fork,
+}
+
+impl ChainIssueKind {
+    fn to_i64(self) -> i64 {
+        match self {
+            ChainIssueKind::Gap => 0,
+            ChainIssueKind::BrokenLink => 1,
+            ChainIssueKind::Fork => 2,
+        }
+    }
+
+    fn from_i64(kind: i64) -> Self {
+        match kind {
+            0 => ChainIssueKind::Gap,
+            1 => ChainIssueKind::BrokenLink,
+            _ => ChainIssueKind::Fork,
+        }
+    }
+}
+
+// Forks are stored as one row per forked msg
+pub struct ChainIssueRow {
+    pub kind: ChainIssueKind,
+    pub from_feed_seq: u64,
+    pub to_feed_seq: u64,
+    pub msg_ref_id: Option<i64>,
+}
+
+pub async fn create_chain_issues_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating chain_issues tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS chain_issues (
+            id INTEGER PRIMARY KEY,
+            feed_ref_id INTEGER NOT NULL,
+            kind INTEGER NOT NULL,
+            from_feed_seq INTEGER NOT NULL,
+            to_feed_seq INTEGER NOT NULL,
+            msg_ref_id INTEGER,
+            FOREIGN KEY (feed_ref_id)
+                REFERENCES feed_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn create_chain_issues_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating chain_issues indices");
+
+    query(
+        "CREATE INDEX IF NOT EXISTS chain_issues_feed_ref_id_index on chain_issues (feed_ref_id, kind)",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// Feeds with indexed msgs
+pub async fn select_feed_ref_ids_with_msgs(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<i64>, Error> {
+    query("SELECT DISTINCT feed_ref_id FROM msgs ORDER BY feed_ref_id")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_all(connection)
+        .await
+}
+
+pub struct FeedChainMsg {
+    pub feed_seq: u64,
+    pub log_seq: Sequence,
+    pub msg_ref_id: i64,
+}
+
+pub async fn select_feed_chain_msgs(
+    connection: &mut SqliteConnection,
+    feed_ref_id: i64,
+) -> Result<Vec<FeedChainMsg>, Error> {
+    query(
+        "
+        SELECT feed_seq, log_seq, msg_ref_id
+        FROM msgs
+        WHERE feed_ref_id = ?
+        ORDER BY feed_seq, log_seq
+        ",
+    )
+    .bind(feed_ref_id)
+    .map(|row: SqliteRow| FeedChainMsg {
+        feed_seq: row.get::<i64, _>(0) as u64,
+        log_seq: row.get::<i64, _>(1) as Sequence,
+        msg_ref_id: row.get(2),
+    })
+    .fetch_all(connection)
+    .await
+}
+
+// Replace the stored issues of a feed with the result of a new validation
+pub async fn replace_chain_issues(
+    connection: &mut SqliteConnection,
+    feed_ref_id: i64,
+    issues: &[ChainIssueRow],
+) -> Result<(), Error> {
+    query("DELETE FROM chain_issues WHERE feed_ref_id = ?")
+        .bind(feed_ref_id)
+        .execute(&mut *connection)
+        .await?;
+
+    for issue in issues {
+        query(
+            "
+            INSERT INTO chain_issues (
+                feed_ref_id,
+                kind,
+                from_feed_seq,
+                to_feed_seq,
+                msg_ref_id
+            ) VALUES (?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(feed_ref_id)
+        .bind(issue.kind.to_i64())
+        .bind(issue.from_feed_seq as i64)
+        .bind(issue.to_feed_seq as i64)
+        .bind(issue.msg_ref_id)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn select_chain_issues_by_feed(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<(ChainIssueRow, Option<MsgRef>)>, Error> {
+    query(
+        "
+        SELECT
+          chain_issues.kind,
+          chain_issues.from_feed_seq,
+          chain_issues.to_feed_seq,
+          chain_issues.msg_ref_id,
+          msg_refs.msg_ref
+        FROM chain_issues
+        JOIN feed_refs ON feed_refs.id = chain_issues.feed_ref_id
+        LEFT JOIN msg_refs ON msg_refs.id = chain_issues.msg_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+        ORDER BY chain_issues.from_feed_seq, chain_issues.id
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .try_map(|row: SqliteRow| {
+        let issue = ChainIssueRow {
+            kind: ChainIssueKind::from_i64(row.get(0)),
+            from_feed_seq: row.get::<i64, _>(1) as u64,
+            to_feed_seq: row.get::<i64, _>(2) as u64,
+            msg_ref_id: row.get(3),
+        };
+        let msg_ref = row
+            .get::<Option<String>, _>(4)
+            .map(decode_msg_ref)
+            .transpose()?;
+        Ok((issue, msg_ref))
+    })
+    .fetch_all(connection)
+    .await
+}
+
+pub async fn select_forked_feeds(connection: &mut SqliteConnection) -> Result<Vec<FeedRef>, Error> {
+    query(
+        "
+        SELECT DISTINCT feed_refs.feed_ref
+        FROM chain_issues
+        JOIN feed_refs ON feed_refs.id = chain_issues.feed_ref_id
+        WHERE
+            chain_issues.kind = ?
+        ",
+    )
+    .bind(ChainIssueKind::Fork.to_i64())
+    .try_map(|row: SqliteRow| decode_feed_ref(row.get(0)))
+    .fetch_all(connection)
+    .await
+}
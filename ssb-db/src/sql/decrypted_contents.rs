@@ -0,0 +1,217 @@
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use hkdf::Hkdf;
+use log::trace;
+use private_box::Keypair;
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{query, sqlite::SqliteRow, Error, QueryBuilder, Row, Sqlite, SqliteConnection};
+use ssb_ref::MsgRef;
+use std::collections::HashMap;
+
+use crate::box2::{secretbox_open, secretbox_seal};
+
+// A key to seal the decrypted contents with, see `Database::new`
+pub type ContentKey = [u8; 32];
+
+// A content key derived from a secret key, so it doesn't have to be stored separately.
+pub fn content_key_from_secret(keypair: &Keypair) -> ContentKey {
+    let mut content_key = [0; 32];
+    Hkdf::<Sha256>::new(None, &keypair.secret.0[..32])
+        .expand(b"ssb-db decrypted contents", &mut content_key)
+        .expect("32 bytes is a valid output length");
+    content_key
+}
+
+// The decrypted content of private msgs is kept so reads don't need the keys again.
+//
+// Without a content key it is stored in the clear. With one it is sealed, but the indexed
+// copies of decrypted msgs are not: their text in search_msgs, their recipients in
+// private_conversations and whatever else the indexers store, e.g. posts, abouts and votes.
+// Either way the db must be kept as private as the secret keys, e.g. on an encrypted disk.
+pub async fn create_decrypted_contents_tables(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Creating decrypted_contents tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS decrypted_contents (
+            msg_ref_id INTEGER PRIMARY KEY,
+            content TEXT NOT NULL,
+            FOREIGN KEY (msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_decrypted_content(
+    connection: &mut SqliteConnection,
+    content_key: Option<&ContentKey>,
+    msg_ref_id: i64,
+    content: &Value,
+) -> Result<(), Error> {
+    trace!("insert decrypted content");
+    let content = match content_key {
+        Some(content_key) => seal_content(content_key, msg_ref_id, content),
+        None => content.to_string(),
+    };
+    query("INSERT OR REPLACE INTO decrypted_contents (msg_ref_id, content) VALUES (?, ?)")
+        .bind(msg_ref_id)
+        .bind(content)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+// Sealed contents are the base64 of a secretbox with a key of their own, as the nonce is
+// always zero. A msg's content never changes, so sealing it again seals the same plaintext.
+fn seal_content(content_key: &ContentKey, msg_ref_id: i64, content: &Value) -> String {
+    let key = msg_content_key(content_key, msg_ref_id);
+    let sealed = secretbox_seal(&key, content.to_string().as_bytes());
+    format!("{}.sealed", b64.encode(sealed))
+}
+
+// The content stored for a msg, or None if it is sealed and the content key can't open it
+pub fn open_decrypted_content(
+    content_key: Option<&ContentKey>,
+    msg_ref_id: i64,
+    content: &str,
+) -> Result<Option<Value>, Error> {
+    let Some(sealed) = content.strip_suffix(".sealed") else {
+        let content = serde_json::from_str(content).map_err(|err| Error::Decode(Box::new(err)))?;
+        return Ok(Some(content));
+    };
+
+    let opened = content_key.and_then(|content_key| {
+        let key = msg_content_key(content_key, msg_ref_id);
+        secretbox_open(&key, &b64.decode(sealed).ok()?)
+    });
+    opened
+        .map(|bytes| serde_json::from_slice(&bytes).map_err(|err| Error::Decode(Box::new(err))))
+        .transpose()
+}
+
+fn msg_content_key(content_key: &ContentKey, msg_ref_id: i64) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(None, content_key)
+        .expand(&msg_ref_id.to_be_bytes(), &mut key)
+        .expect("32 bytes is a valid output length");
+    key
+}
+
+// The decrypted contents of the msgs that have one the content key can open, by msg ref
+pub async fn select_decrypted_contents(
+    connection: &mut SqliteConnection,
+    content_key: Option<&ContentKey>,
+    msg_refs: &[MsgRef],
+) -> Result<HashMap<String, Value>, Error> {
+    if msg_refs.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "
+        SELECT msg_refs.msg_ref, decrypted_contents.msg_ref_id, decrypted_contents.content
+        FROM decrypted_contents
+        JOIN msg_refs ON msg_refs.id = decrypted_contents.msg_ref_id
+        WHERE msg_refs.msg_ref IN (",
+    );
+    let mut decrypted = builder.separated(", ");
+    for msg_ref in msg_refs {
+        decrypted.push_bind(Into::<String>::into(msg_ref));
+    }
+    builder.push(")");
+
+    builder
+        .build()
+        .try_map(|row: SqliteRow| {
+            let content = open_decrypted_content(content_key, row.get(1), row.get(2))?;
+            Ok(content.map(|content| (row.get(0), content)))
+        })
+        .fetch_all(connection)
+        .await
+        .map(|contents| contents.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use ssb_msg::keypair_feed_ref;
+
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Database;
+
+    #[tokio::test]
+    async fn selects_the_contents_of_decrypted_msgs_only() {
+        let dir = temp_dir();
+        let me = keypair(1);
+        let friend = keypair(2);
+        let dm = append_dm(
+            &dir,
+            &friend,
+            &keypair_feed_ref(&me),
+            json!({ "type": "post", "text": "hi" }),
+        );
+        let mut db = open_db(&dir, vec![private_keypair(&me)], Vec::new()).await;
+        let public = db.publish(&me, &post("hello")).await.unwrap();
+        let mut sql = db.reader().acquire().await.unwrap();
+
+        let contents = select_decrypted_contents(&mut sql, None, &[dm.key.clone(), public.key])
+            .await
+            .unwrap();
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[&dm.key.to_string()]["text"], "hi");
+    }
+
+    #[tokio::test]
+    async fn seals_contents_with_the_content_key() {
+        let dir = temp_dir();
+        let me = keypair(1);
+        let friend = keypair(2);
+        let dm = append_dm(
+            &dir,
+            &friend,
+            &keypair_feed_ref(&me),
+            json!({ "type": "post", "text": "hi" }),
+        );
+        let content_key = content_key_from_secret(&private_keypair(&me));
+        let keys = vec![private_keypair(&me)];
+        let mut db = Database::new(
+            log_path(&dir),
+            sql_path(&dir),
+            keys,
+            Some(content_key),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        db.process(u64::MAX).await.unwrap();
+        let reader = db.reader();
+
+        let mut sql = reader.acquire().await.unwrap();
+        let stored: String = query("SELECT content FROM decrypted_contents")
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(&mut sql)
+            .await
+            .unwrap();
+        assert!(stored.ends_with(".sealed"));
+        assert!(!stored.contains("\"text\""));
+        let msg = reader.get_msg(dm.key.clone()).await.unwrap().unwrap();
+        assert_eq!(msg.value.content["text"], "hi");
+
+        // without the key the content reads as still encrypted
+        let contents = select_decrypted_contents(&mut sql, None, std::slice::from_ref(&dm.key))
+            .await
+            .unwrap();
+        assert!(contents.is_empty());
+    }
+}
@@ -2,15 +2,22 @@ use log::trace;
 use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
 use ssb_ref::FeedRef;
 
-pub async fn find_or_create_feed_ref(
+pub async fn find_feed_ref(
     connection: &mut SqliteConnection,
     feed_ref: &FeedRef,
-) -> Result<i64, Error> {
-    let result: Option<i64> = query("SELECT id FROM feed_refs WHERE feed_ref = ?1")
+) -> Result<Option<i64>, Error> {
+    query("SELECT id FROM feed_refs WHERE feed_ref = ?1")
         .bind(Into::<String>::into(feed_ref))
         .map(|row: SqliteRow| row.get(0))
-        .fetch_optional(&mut *connection)
-        .await?;
+        .fetch_optional(connection)
+        .await
+}
+
+pub async fn find_or_create_feed_ref(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<i64, Error> {
+    let result = find_feed_ref(&mut *connection, feed_ref).await?;
 
     if let Some(found_feed_ref) = result {
         Ok(found_feed_ref)
@@ -24,6 +31,10 @@ pub async fn find_or_create_feed_ref(
     }
 }
 
+pub fn decode_feed_ref(feed_ref: String) -> Result<FeedRef, Error> {
+    FeedRef::from_string(feed_ref).map_err(|err| Error::Decode(Box::new(err)))
+}
+
 pub async fn create_feed_refs_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
     trace!("Creating feed_refs tables");
 
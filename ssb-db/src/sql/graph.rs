@@ -0,0 +1,194 @@
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::FeedRef;
+
+use crate::sql::*;
+
+// contacts.state is 1 for following, 0 for neither and -1 for blocking
+
+pub async fn select_following(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    select_contacts(
+        connection,
+        "
+        SELECT DISTINCT contact_feed_refs.feed_ref
+        FROM contacts
+        JOIN feed_refs ON feed_refs.id = contacts.feed_ref_id
+        JOIN feed_refs AS contact_feed_refs ON contact_feed_refs.id = contacts.contact_feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+            AND contacts.state = 1
+        ",
+        feed_ref,
+    )
+    .await
+}
+
+pub async fn select_followers(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    select_contacts(
+        connection,
+        "
+        SELECT DISTINCT feed_refs.feed_ref
+        FROM contacts
+        JOIN feed_refs ON feed_refs.id = contacts.feed_ref_id
+        JOIN feed_refs AS contact_feed_refs ON contact_feed_refs.id = contacts.contact_feed_ref_id
+        WHERE
+            contact_feed_refs.feed_ref = ?
+            AND contacts.state = 1
+        ",
+        feed_ref,
+    )
+    .await
+}
+
+// Feeds that follow and are followed by the feed
+pub async fn select_friends(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    select_contacts(
+        connection,
+        "
+        SELECT DISTINCT contact_feed_refs.feed_ref
+        FROM contacts
+        JOIN feed_refs ON feed_refs.id = contacts.feed_ref_id
+        JOIN feed_refs AS contact_feed_refs ON contact_feed_refs.id = contacts.contact_feed_ref_id
+        JOIN contacts AS back_contacts
+            ON back_contacts.feed_ref_id = contacts.contact_feed_ref_id
+            AND back_contacts.contact_feed_ref_id = contacts.feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+            AND contacts.state = 1
+            AND back_contacts.state = 1
+        ",
+        feed_ref,
+    )
+    .await
+}
+
+pub async fn select_blocking(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    select_contacts(
+        connection,
+        "
+        SELECT DISTINCT contact_feed_refs.feed_ref
+        FROM contacts
+        JOIN feed_refs ON feed_refs.id = contacts.feed_ref_id
+        JOIN feed_refs AS contact_feed_refs ON contact_feed_refs.id = contacts.contact_feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+            AND contacts.state = -1
+        ",
+        feed_ref,
+    )
+    .await
+}
+
+pub async fn select_blocked_by(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    select_contacts(
+        connection,
+        "
+        SELECT DISTINCT feed_refs.feed_ref
+        FROM contacts
+        JOIN feed_refs ON feed_refs.id = contacts.feed_ref_id
+        JOIN feed_refs AS contact_feed_refs ON contact_feed_refs.id = contacts.contact_feed_ref_id
+        WHERE
+            contact_feed_refs.feed_ref = ?
+            AND contacts.state = -1
+        ",
+        feed_ref,
+    )
+    .await
+}
+
+async fn select_contacts(
+    connection: &mut SqliteConnection,
+    sql: &str,
+    feed_ref: &FeedRef,
+) -> Result<Vec<FeedRef>, Error> {
+    query(sql)
+        .bind(Into::<String>::into(feed_ref))
+        .try_map(|row: SqliteRow| decode_feed_ref(row.get(0)))
+        .fetch_all(connection)
+        .await
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedHops {
+    pub feed_ref: FeedRef,
+    pub hops: u32,
+}
+
+// The feeds up to ?2 follows away from the feed ?1 as `hops(feed_ref_id, hops)`, with a row
+// for each distance a feed is found at. Feeds blocked by ?1 are left out, and are not
+// followed through.
+pub(crate) const HOPS_CTE: &str = "
+        WITH RECURSIVE
+          source(id) AS (
+            SELECT id FROM feed_refs WHERE feed_ref = ?1
+          ),
+          blocked(id) AS (
+            SELECT contact_feed_ref_id
+            FROM contacts
+            WHERE
+                feed_ref_id = (SELECT id FROM source)
+                AND state = -1
+          ),
+          hops(feed_ref_id, hops) AS (
+            SELECT id, 0 FROM source
+            UNION
+            SELECT contacts.contact_feed_ref_id, hops.hops + 1
+            FROM hops
+            JOIN contacts ON contacts.feed_ref_id = hops.feed_ref_id
+            WHERE
+                contacts.state = 1
+                AND hops.hops < ?2
+                AND contacts.contact_feed_ref_id NOT IN (SELECT id FROM blocked)
+          )";
+
+// Follow the graph out from a feed, up to max_hops follows away.
+//
+// Feeds blocked by the starting feed are left out, and are not followed through. The
+// starting feed is at 0 hops. If to_feed_ref is given, only that feed is returned.
+pub async fn select_hops(
+    connection: &mut SqliteConnection,
+    from_feed_ref: &FeedRef,
+    to_feed_ref: Option<&FeedRef>,
+    max_hops: u32,
+) -> Result<Vec<FeedHops>, Error> {
+    query(&format!(
+        "
+        {}
+        SELECT
+          feed_refs.feed_ref,
+          MIN(hops.hops) AS min_hops
+        FROM hops
+        JOIN feed_refs ON feed_refs.id = hops.feed_ref_id
+        WHERE
+            ?3 IS NULL OR feed_refs.feed_ref = ?3
+        GROUP BY hops.feed_ref_id
+        ORDER BY min_hops, feed_refs.id
+        ",
+        HOPS_CTE
+    ))
+    .bind(Into::<String>::into(from_feed_ref))
+    .bind(max_hops)
+    .bind(to_feed_ref.map(Into::<String>::into))
+    .try_map(|row: SqliteRow| {
+        Ok(FeedHops {
+            feed_ref: decode_feed_ref(row.get(0))?,
+            hops: row.get(1),
+        })
+    })
+    .fetch_all(connection)
+    .await
+}
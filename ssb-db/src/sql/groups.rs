@@ -0,0 +1,185 @@
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use log::trace;
+use serde_json::Value;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_msg::{GroupAddMemberContent, Msg};
+use ssb_ref::{FeedRef, MsgRef};
+
+use crate::box2::GroupKey;
+use crate::sql::*;
+
+// Private groups are learned from the `group/add-member` msgs that invite us, which carry the
+// group key used to decrypt the msgs of the group.
+
+#[derive(Debug)]
+pub struct Group {
+    // The cloaked id used as the first recipient of group msgs, e.g. `%...=.cloaked`
+    pub group_ref: String,
+    pub root: MsgRef,
+}
+
+pub async fn create_groups_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating groups tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS groups (
+            id INTEGER PRIMARY KEY,
+            group_ref TEXT UNIQUE NOT NULL,
+            group_key BLOB NOT NULL,
+            root_msg_ref_id INTEGER NOT NULL,
+            FOREIGN KEY (root_msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS group_members (
+            id INTEGER PRIMARY KEY,
+            group_id INTEGER NOT NULL,
+            feed_ref_id INTEGER NOT NULL,
+            added_by_msg_ref_id INTEGER NOT NULL,
+            FOREIGN KEY (group_id)
+                REFERENCES groups (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (feed_ref_id)
+                REFERENCES feed_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (added_by_msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn create_groups_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating groups indices");
+
+    query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS group_members_group_id_feed_ref_id_index on group_members (group_id, feed_ref_id)",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// Store the group and its new members, the author of the msg is a member too.
+pub async fn insert_group_members(
+    connection: &mut SqliteConnection,
+    msg: &Msg<Value>,
+    add_member: &GroupAddMemberContent,
+    msg_ref_id: i64,
+) -> Result<(), Error> {
+    let Some(group_ref) = add_member
+        .recps
+        .first()
+        .filter(|recp| recp.ends_with(".cloaked"))
+    else {
+        return Ok(());
+    };
+    let Some(group_key) = b64
+        .decode(&add_member.group_key)
+        .ok()
+        .filter(|group_key| group_key.len() == 32)
+    else {
+        return Ok(());
+    };
+
+    trace!("insert group");
+    let root_msg_ref_id = find_or_create_msg_ref(&mut *connection, &add_member.root).await?;
+    query("INSERT OR IGNORE INTO groups (group_ref, group_key, root_msg_ref_id) VALUES (?, ?, ?)")
+        .bind(group_ref)
+        .bind(group_key)
+        .bind(root_msg_ref_id)
+        .execute(&mut *connection)
+        .await?;
+    let group_id: i64 = query("SELECT id FROM groups WHERE group_ref = ?")
+        .bind(group_ref)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(&mut *connection)
+        .await?;
+
+    let members = add_member.recps[1..]
+        .iter()
+        .filter_map(|recp| FeedRef::from_string(recp.clone()).ok())
+        .chain([msg.value.author.clone()]);
+    for member in members {
+        let feed_ref_id = find_or_create_feed_ref(&mut *connection, &member).await?;
+        query("INSERT OR IGNORE INTO group_members (group_id, feed_ref_id, added_by_msg_ref_id) VALUES (?, ?, ?)")
+            .bind(group_id)
+            .bind(feed_ref_id)
+            .bind(msg_ref_id)
+            .execute(&mut *connection)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Keys of the known groups, in the order they were learned
+pub async fn select_group_keys(connection: &mut SqliteConnection) -> Result<Vec<GroupKey>, Error> {
+    let group_keys: Vec<Vec<u8>> = query("SELECT group_key FROM groups ORDER BY id")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_all(connection)
+        .await?;
+
+    Ok(group_keys
+        .into_iter()
+        .filter_map(|group_key| group_key.try_into().ok())
+        .collect())
+}
+
+pub async fn select_groups(connection: &mut SqliteConnection) -> Result<Vec<Group>, Error> {
+    query(
+        "
+        SELECT groups.group_ref, msg_refs.msg_ref
+        FROM groups
+        JOIN msg_refs ON msg_refs.id = groups.root_msg_ref_id
+        ORDER BY groups.id
+        ",
+    )
+    .try_map(|row: SqliteRow| {
+        Ok(Group {
+            group_ref: row.get(0),
+            root: decode_msg_ref(row.get(1))?,
+        })
+    })
+    .fetch_all(connection)
+    .await
+}
+
+// Members of a group, in the order they were added
+pub async fn select_group_members(
+    connection: &mut SqliteConnection,
+    group_ref: &str,
+) -> Result<Vec<FeedRef>, Error> {
+    query(
+        "
+        SELECT feed_refs.feed_ref
+        FROM group_members
+        JOIN groups ON groups.id = group_members.group_id
+        JOIN feed_refs ON feed_refs.id = group_members.feed_ref_id
+        WHERE groups.group_ref = ?1
+        ORDER BY group_members.id
+        ",
+    )
+    .bind(group_ref)
+    .try_map(|row: SqliteRow| decode_feed_ref(row.get(0)))
+    .fetch_all(connection)
+    .await
+}
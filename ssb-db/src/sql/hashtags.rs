@@ -0,0 +1,205 @@
+use log::trace;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_msg::{Link, PostContent};
+use ssb_ref::HashtagRef;
+
+use crate::sql::*;
+
+// Hashtag mentions, inline #tags in the text and the channel of a post are all stored as
+// the same normalised tag, so #Rust, `channel: "rust"` and a mention of #rust all match.
+
+pub async fn create_hashtags_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating hashtags tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS hashtags (
+            id INTEGER PRIMARY KEY,
+            hashtag TEXT UNIQUE NOT NULL
+        )
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS hashtag_links (
+            id INTEGER PRIMARY KEY,
+            link_from_msg_ref_id INTEGER NOT NULL,
+            link_to_hashtag_id INTEGER NOT NULL,
+            FOREIGN KEY (link_from_msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (link_to_hashtag_id)
+                REFERENCES hashtags (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn create_hashtags_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating hashtags indices");
+
+    query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS hashtag_links_from_to_index on hashtag_links (link_from_msg_ref_id, link_to_hashtag_id)",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    query(
+        "CREATE INDEX IF NOT EXISTS hashtag_links_to_hashtag_id_index on hashtag_links (link_to_hashtag_id)",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// Lowercase and strip the leading #, None if nothing is left.
+pub fn normalize_hashtag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches(['#', '＃']).trim();
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag.to_lowercase())
+    }
+}
+
+fn collect_hashtags(post: &PostContent) -> Vec<String> {
+    let mentioned = post
+        .mentions
+        .iter()
+        .flatten()
+        .filter_map(|link| match link {
+            Link::Hashtag { link } => Some(link.parse_tag()),
+            _ => None,
+        });
+    let inline = HashtagRef::multi_regex()
+        .captures_iter(&post.text)
+        .filter_map(|caps| caps.name("tag").map(|tag| tag.as_str().to_string()));
+
+    let mut hashtags: Vec<String> = mentioned
+        .chain(inline)
+        .chain(post.channel.clone())
+        .filter_map(|tag| normalize_hashtag(&tag))
+        .collect();
+    hashtags.sort();
+    hashtags.dedup();
+    hashtags
+}
+
+async fn find_or_create_hashtag(
+    connection: &mut SqliteConnection,
+    hashtag: &str,
+) -> Result<i64, Error> {
+    let result: Option<i64> = query("SELECT id FROM hashtags WHERE hashtag = ?")
+        .bind(hashtag)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_optional(&mut *connection)
+        .await?;
+
+    if let Some(found_hashtag) = result {
+        Ok(found_hashtag)
+    } else {
+        let created_hashtag = query("INSERT INTO hashtags (hashtag) VALUES (?)")
+            .bind(hashtag)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(created_hashtag.last_insert_rowid())
+    }
+}
+
+pub async fn insert_hashtags(
+    connection: &mut SqliteConnection,
+    post: &PostContent,
+    msg_ref_id: i64,
+) -> Result<(), Error> {
+    for hashtag in collect_hashtags(post) {
+        let hashtag_id = find_or_create_hashtag(&mut *connection, &hashtag).await?;
+        query("INSERT OR IGNORE INTO hashtag_links (link_from_msg_ref_id, link_to_hashtag_id) VALUES (?, ?)")
+            .bind(msg_ref_id)
+            .bind(hashtag_id)
+            .execute(&mut *connection)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub struct SelectMsgsByHashtagOptions<'a> {
+    // Normalised the same way as when indexing
+    pub hashtag: &'a str,
+    pub page_size: i64,
+    pub less_than_log_seq: Option<Sequence>,
+}
+
+pub async fn select_msg_log_seqs_by_hashtag<'a>(
+    connection: &mut SqliteConnection,
+    options: SelectMsgsByHashtagOptions<'a>,
+) -> Result<Vec<Sequence>, Error> {
+    let Some(hashtag) = normalize_hashtag(options.hashtag) else {
+        return Ok(Vec::new());
+    };
+
+    query(
+        "
+        SELECT msgs.log_seq
+        FROM hashtag_links
+        JOIN hashtags ON hashtags.id = hashtag_links.link_to_hashtag_id
+        JOIN msgs ON msgs.msg_ref_id = hashtag_links.link_from_msg_ref_id
+        WHERE
+            hashtags.hashtag = ?1
+            AND (?2 IS NULL OR msgs.log_seq < ?2)
+        ORDER BY msgs.log_seq DESC
+        LIMIT ?3
+        ",
+    )
+    .bind(hashtag)
+    .bind(options.less_than_log_seq.map(|log_seq| log_seq as i64))
+    .bind(options.page_size)
+    .map(|row: SqliteRow| row.get::<i64, _>(0) as Sequence)
+    .fetch_all(connection)
+    .await
+}
+
+#[derive(Debug)]
+pub struct HashtagCount {
+    pub hashtag: String,
+    pub count: i64,
+}
+
+// Tags used on the most msgs
+pub async fn select_popular_hashtags(
+    connection: &mut SqliteConnection,
+    limit: i64,
+) -> Result<Vec<HashtagCount>, Error> {
+    query(
+        "
+        SELECT
+          hashtags.hashtag,
+          COUNT(*) AS count
+        FROM hashtag_links
+        JOIN hashtags ON hashtags.id = hashtag_links.link_to_hashtag_id
+        GROUP BY hashtags.id
+        ORDER BY count DESC, hashtags.hashtag
+        LIMIT ?
+        ",
+    )
+    .bind(limit)
+    .map(|row: SqliteRow| HashtagCount {
+        hashtag: row.get(0),
+        count: row.get(1),
+    })
+    .fetch_all(connection)
+    .await
+}
@@ -0,0 +1,296 @@
+use futures::future::BoxFuture;
+use sqlx::{query, Error, SqliteConnection};
+use ssb_msg::{BlobLink, Link, MsgContent};
+use std::sync::Arc;
+
+use crate::indexer::{IndexedMsg, Indexer};
+use crate::sql::*;
+
+// The indexers for the content types ssb-db knows, in the order they run.
+pub fn builtin_indexers() -> Vec<Arc<dyn Indexer>> {
+    vec![
+        Arc::new(PostIndexer),
+        Arc::new(ContactIndexer),
+        Arc::new(VoteIndexer),
+        Arc::new(AboutIndexer),
+        Arc::new(GroupIndexer),
+    ]
+}
+
+// Posts with their mentions, search text, hashtags, branches and threads
+pub struct PostIndexer;
+
+impl Indexer for PostIndexer {
+    fn name(&self) -> &str {
+        "posts"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            create_posts_tables(connection).await?;
+            create_post_branches_tables(connection).await?;
+            create_hashtags_tables(connection).await?;
+            create_threads_tables(connection).await
+        })
+    }
+
+    fn create_indices<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            create_posts_indices(connection).await?;
+            create_post_branches_indices(connection).await?;
+            create_hashtags_indices(connection).await?;
+            create_threads_indices(connection).await
+        })
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            let MsgContent::Post(post) = msg.content else {
+                return Ok(());
+            };
+
+            if let Some(links) = &post.mentions {
+                let mut msg_refs = Vec::new();
+                let mut feed_refs = Vec::new();
+                let mut blob_refs = Vec::new();
+                for link in links.iter() {
+                    match link {
+                        Link::Msg { link, .. } => msg_refs.push(link),
+                        Link::Feed { link, .. } => feed_refs.push(link),
+                        Link::Blob(BlobLink { link, .. }) => blob_refs.push(link),
+                        // indexed with the inline tags and channel by insert_hashtags
+                        Link::Hashtag { .. } => {}
+                    }
+                }
+                insert_links(connection, msg_refs.as_slice(), msg.msg_ref_id).await?;
+                insert_feed_links(connection, feed_refs.as_slice(), msg.msg_ref_id).await?;
+                insert_blob_links(connection, blob_refs.as_slice(), msg.msg_ref_id).await?;
+            }
+
+            insert_post(connection, msg.msg, post, msg.msg_ref_id, msg.is_decrypted).await?;
+            insert_post_search(connection, post, msg.msg_ref_id).await?;
+            insert_hashtags(connection, post, msg.msg_ref_id).await?;
+            if let Some(branch) = &post.branch {
+                insert_post_branches(connection, branch.as_slice(), msg.msg_ref_id).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // The search text is replaced when the posts are indexed again, so it is kept, as are
+    // the hashtags the links point to
+    fn clear<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), Error>>> {
+        Some(Box::pin(delete_all(
+            connection,
+            &[
+                "thread_participants",
+                "threads",
+                "hashtag_links",
+                "post_branches",
+                "posts",
+                "msg_links",
+                "feed_links",
+                "blob_links",
+            ],
+        )))
+    }
+}
+
+pub struct ContactIndexer;
+
+impl Indexer for ContactIndexer {
+    fn name(&self) -> &str {
+        "contacts"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_contacts_tables(connection))
+    }
+
+    fn create_indices<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_contacts_indices(connection))
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            let MsgContent::Contact(contact) = msg.content else {
+                return Ok(());
+            };
+            insert_or_update_contacts(
+                connection,
+                msg.msg,
+                contact,
+                msg.msg_ref_id,
+                msg.is_decrypted,
+            )
+            .await
+        })
+    }
+
+    // Contacts are applied in log order, so replaying them restores the latest state
+    fn clear<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), Error>>> {
+        Some(Box::pin(delete_all(connection, &["contacts"])))
+    }
+}
+
+pub struct VoteIndexer;
+
+impl Indexer for VoteIndexer {
+    fn name(&self) -> &str {
+        "votes"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_votes_tables(connection))
+    }
+
+    fn create_indices<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_votes_indices(connection))
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            let MsgContent::Vote(vote) = msg.content else {
+                return Ok(());
+            };
+            insert_or_update_votes(connection, msg.msg, vote).await
+        })
+    }
+
+    fn clear<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), Error>>> {
+        Some(Box::pin(delete_all(connection, &["votes"])))
+    }
+}
+
+pub struct AboutIndexer;
+
+impl Indexer for AboutIndexer {
+    fn name(&self) -> &str {
+        "abouts"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_abouts_tables(connection))
+    }
+
+    fn create_indices<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_abouts_indices(connection))
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            let MsgContent::About(about) = msg.content else {
+                return Ok(());
+            };
+            insert_abouts(connection, msg.msg, about).await?;
+            insert_about_search(connection, about, msg.msg_ref_id).await
+        })
+    }
+
+    // The search text is replaced when the abouts are indexed again, so it is kept
+    fn clear<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), Error>>> {
+        Some(Box::pin(delete_all(
+            connection,
+            &["about_feeds", "about_msgs", "about_feed_names"],
+        )))
+    }
+}
+
+// Groups can't be rebuilt, their keys were used to decrypt the msgs already indexed
+pub struct GroupIndexer;
+
+impl Indexer for GroupIndexer {
+    fn name(&self) -> &str {
+        "groups"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_groups_tables(connection))
+    }
+
+    fn create_indices<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(create_groups_indices(connection))
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), Error>> {
+        Box::pin(async move {
+            let MsgContent::GroupAddMember(add_member) = msg.content else {
+                return Ok(());
+            };
+            insert_group_members(connection, msg.msg, add_member, msg.msg_ref_id).await
+        })
+    }
+}
+
+async fn delete_all(connection: &mut SqliteConnection, tables: &[&str]) -> Result<(), Error> {
+    for table in tables {
+        query(&format!("DELETE FROM {}", table))
+            .execute(&mut *connection)
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::FeedRef;
+
+use crate::sql::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MentionKind {
+    // A msg that links to the feed in its mentions
+    Mention,
+    // A post in a thread the feed started or replied to
+    Reply,
+    // A vote on one of the feed's msgs
+    Vote,
+}
+
+impl MentionKind {
+    fn from_i64(kind: i64) -> Self {
+        match kind {
+            0 => MentionKind::Mention,
+            1 => MentionKind::Reply,
+            _ => MentionKind::Vote,
+        }
+    }
+}
+
+// Position in a list ordered by received time, newest first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReceivedCursor {
+    pub timestamp_received: f64,
+    pub log_seq: Sequence,
+}
+
+pub struct MentionRow {
+    pub kind: MentionKind,
+    pub cursor: ReceivedCursor,
+}
+
+// Msgs by other feeds that notify the feed, received before the cursor.
+//
+// A msg that is both a mention and a reply is only returned once, as a mention.
+pub async fn select_mentions(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+    before: Option<ReceivedCursor>,
+    page_size: i64,
+) -> Result<Vec<MentionRow>, Error> {
+    query(
+        "
+        WITH
+        feed AS (
+            SELECT id FROM feed_refs WHERE feed_ref = ?1
+        ),
+        threads AS (
+            SELECT msgs.msg_ref_id AS root_msg_ref_id
+            FROM msgs, feed
+            WHERE
+                msgs.feed_ref_id = feed.id
+                AND msgs.content_type = 'post'
+            UNION
+            SELECT posts.root_msg_ref_id
+            FROM posts
+            JOIN msgs ON msgs.msg_ref_id = posts.msg_ref_id, feed
+            WHERE
+                msgs.feed_ref_id = feed.id
+                AND posts.root_msg_ref_id IS NOT NULL
+        ),
+        -- each branch only keeps msgs before the cursor, so pages don't union every
+        -- notification of the feed
+        notifications AS (
+            SELECT msgs.log_seq, msgs.timestamp_received, 0 AS kind
+            FROM feed_links
+            JOIN msgs ON msgs.msg_ref_id = feed_links.link_from_msg_ref_id, feed
+            WHERE
+                feed_links.link_to_feed_ref_id = feed.id
+                AND msgs.feed_ref_id != feed.id
+                AND (?2 IS NULL OR (msgs.timestamp_received, msgs.log_seq) < (?2, ?3))
+            UNION ALL
+            SELECT msgs.log_seq, msgs.timestamp_received, 1 AS kind
+            FROM posts
+            JOIN threads ON threads.root_msg_ref_id = posts.root_msg_ref_id
+            JOIN msgs ON msgs.msg_ref_id = posts.msg_ref_id, feed
+            WHERE
+                msgs.feed_ref_id != feed.id
+                AND (?2 IS NULL OR (msgs.timestamp_received, msgs.log_seq) < (?2, ?3))
+            UNION ALL
+            SELECT msgs.log_seq, msgs.timestamp_received, 2 AS kind
+            FROM votes
+            JOIN msgs AS voted_msgs ON voted_msgs.msg_ref_id = votes.link_to_msg_ref_id
+            JOIN msgs
+                ON msgs.feed_ref_id = votes.link_from_feed_ref_id
+                AND msgs.feed_seq = votes.feed_seq, feed
+            WHERE
+                voted_msgs.feed_ref_id = feed.id
+                AND votes.value > 0
+                AND msgs.feed_ref_id != feed.id
+                AND (?2 IS NULL OR (msgs.timestamp_received, msgs.log_seq) < (?2, ?3))
+        )
+        SELECT log_seq, timestamp_received, MIN(kind)
+        FROM notifications
+        GROUP BY log_seq
+        ORDER BY timestamp_received DESC, log_seq DESC
+        LIMIT ?4
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .bind(before.map(|cursor| cursor.timestamp_received))
+    .bind(before.map(|cursor| cursor.log_seq as i64))
+    .bind(page_size)
+    .map(|row: SqliteRow| MentionRow {
+        kind: MentionKind::from_i64(row.get(2)),
+        cursor: ReceivedCursor {
+            timestamp_received: row.get(1),
+            log_seq: row.get::<i64, _>(0) as Sequence,
+        },
+    })
+    .fetch_all(connection)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use ssb_msg::keypair_feed_ref;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[tokio::test]
+    async fn pages_through_mentions_replies_and_votes() {
+        let dir = temp_dir();
+        let (alice, bob, carol) = (keypair(1), keypair(2), keypair(3));
+        let alice_feed = keypair_feed_ref(&alice);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let root = db.publish(&alice, &post("root")).await.unwrap();
+        let reply = |text: &str| {
+            from_value(json!({
+                "type": "post",
+                "text": text,
+                "root": root.key.to_string(),
+                "branch": root.key.to_string(),
+            }))
+            .unwrap()
+        };
+        let vote = from_value(json!({
+            "type": "vote",
+            "vote": { "link": root.key.to_string(), "value": 1, "expression": "Like" },
+        }))
+        .unwrap();
+        let mention = from_value(json!({
+            "type": "post",
+            "text": "hi",
+            "mentions": [{ "link": Into::<String>::into(&alice_feed) }],
+        }))
+        .unwrap();
+        db.publish(&bob, &reply("reply")).await.unwrap();
+        db.publish(&carol, &vote).await.unwrap();
+        db.publish(&bob, &mention).await.unwrap();
+        // alice's own msgs don't notify her
+        db.publish(&alice, &reply("own reply")).await.unwrap();
+
+        let mut kinds = Vec::new();
+        let mut before = None;
+        loop {
+            let rows = select_mentions(&mut db.sql, &alice_feed, before, 1)
+                .await
+                .unwrap();
+            let Some(row) = rows.last() else {
+                break;
+            };
+            before = Some(row.cursor);
+            kinds.extend(rows.iter().map(|row| row.kind));
+        }
+
+        assert_eq!(
+            kinds,
+            [MentionKind::Mention, MentionKind::Vote, MentionKind::Reply]
+        );
+    }
+}
@@ -1,9 +1,170 @@
-use log::trace;
-use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use flumedb::{FlumeLog, OffsetLog};
+use futures::future::BoxFuture;
+use log::{info, trace};
+use private_box::Keypair;
+use serde_json::Value;
+use sqlx::{query, sqlite::SqliteRow, Connection, Error as SqlError, Row, SqliteConnection};
+use ssb_msg::Msg;
+use std::sync::Arc;
 
-const MIGRATION_VERSION_NUMBER: u32 = 1;
+use crate::indexer::Indexer;
+use crate::sql::*;
+use crate::{attempt_decryption, decrypt_indexed_msgs, rebuild_indexers, select_dm_recps, Error};
 
-pub async fn create_migrations_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+// The version of a db created from scratch by `setup_new_db`.
+const INITIAL_VERSION_NUMBER: u32 = 1;
+
+// Migrations are run in order, each one moving the db from `version - 1` to `version`.
+//
+// When adding a table or index, add it to the `create_*` functions used by `setup_new_db`,
+// or by the built-in indexer it belongs to, and add a migration here that creates it on
+// existing dbs.
+//
+// A migration only writes to the tables it creates or alters. Tables of later migrations
+// may be missing columns the current insert functions write, so anything that needs the
+// indexers, or decryption that indexes what it decrypts, is left to `Backfill::Reindex` and
+// `Backfill::Decrypt`, which run once every migration has.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        description: "full-text search of posts and abouts",
+        up: |connection| Box::pin(create_search_tables(connection)),
+        backfill: Backfill::Reindex(&["posts", "abouts"]),
+    },
+    Migration {
+        version: 3,
+        description: "index post branches for threads",
+        up: |connection| Box::pin(create_post_branches_indices(connection)),
+        backfill: Backfill::None,
+    },
+    Migration {
+        version: 4,
+        description: "merge abouts correctly and keep self-assigned name history",
+        up: |connection| Box::pin(create_about_feed_names_schema(connection)),
+        // abouts used to be merged with older values winning
+        backfill: Backfill::Reindex(&["abouts"]),
+    },
+    Migration {
+        version: 5,
+        description: "keep vote expressions",
+        up: |connection| Box::pin(add_votes_expression_column(connection)),
+        // votes are applied in log order, so the latest vote of each feed sets the expression
+        backfill: Backfill::Reindex(&["votes"]),
+    },
+    Migration {
+        version: 6,
+        description: "index hashtags and channels of posts",
+        up: |connection| Box::pin(create_hashtags_schema(connection)),
+        backfill: Backfill::Reindex(&["posts"]),
+    },
+    Migration {
+        version: 7,
+        description: "store feed chain validation results",
+        up: |connection| Box::pin(create_chain_issues_schema(connection)),
+        // feeds are validated on demand
+        backfill: Backfill::None,
+    },
+    Migration {
+        version: 8,
+        description: "keep the decrypted content of private msgs",
+        up: |connection| Box::pin(create_decrypted_contents_tables(connection)),
+        backfill: Backfill::Rust(backfill_decrypted_contents),
+    },
+    Migration {
+        version: 9,
+        description: "decrypt private group msgs and keep group members",
+        up: |connection| Box::pin(create_groups_schema(connection)),
+        // box2 msgs used to be skipped
+        backfill: Backfill::Decrypt,
+    },
+    Migration {
+        version: 10,
+        description: "group private msgs into conversations by recipients",
+        up: |connection| Box::pin(create_private_conversations_schema(connection)),
+        backfill: Backfill::Rust(backfill_private_conversations),
+    },
+    Migration {
+        version: 11,
+        description: "keep the msg count and latest msg of private conversations",
+        // the summaries of existing conversations are filled with the new columns
+        up: |connection| Box::pin(create_private_conversations_summary_schema(connection)),
+        backfill: Backfill::None,
+    },
+    Migration {
+        version: 12,
+        description: "index msgs by feed and sequence",
+        up: |connection| Box::pin(create_msgs_feed_seq_index(connection)),
+        backfill: Backfill::None,
+    },
+    Migration {
+        version: 13,
+        description: "index msgs by received and asserted time",
+        up: |connection| Box::pin(create_msgs_timestamp_indices(connection)),
+        backfill: Backfill::None,
+    },
+    Migration {
+        version: 14,
+        description: "keep a summary of each thread",
+        up: |connection| Box::pin(create_threads_schema(connection)),
+        backfill: Backfill::Reindex(&["posts"]),
+    },
+    Migration {
+        version: 15,
+        description: "order abouts by when they were received",
+        up: |connection| Box::pin(add_about_feeds_timestamp_received_column(connection)),
+        backfill: Backfill::Reindex(&["abouts"]),
+    },
+];
+
+// Number of msgs read from the offset log at a time during a backfill.
+const BACKFILL_PAGE_SIZE: i64 = 1_000;
+
+pub type MigrationFn = for<'c> fn(&'c mut SqliteConnection) -> BoxFuture<'c, Result<(), SqlError>>;
+
+pub type BackfillFn = for<'c> fn(
+    &'c mut SqliteConnection,
+    &'c OffsetLog<u32>,
+    &'c [Keypair],
+    Option<&'c ContentKey>,
+) -> BoxFuture<'c, Result<(), Error>>;
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub up: MigrationFn,
+    pub backfill: Backfill,
+}
+
+pub enum Backfill {
+    // The new schema is complete without looking at existing msgs
+    None,
+    // Fill the tables of the migration from existing rows or from the offset log
+    Rust(BackfillFn),
+    // Clear the built-in indexers with these names and index every readable msg with them
+    // again, once the last migration has run
+    Reindex(&'static [&'static str]),
+    // Try the keys on the msgs none could decrypt and index the ones they decrypt with every
+    // indexer, once the last migration has run
+    Decrypt,
+    // The new schema can't be filled for existing msgs, so the db must be rebuilt
+    Impossible,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MigrateOutcome {
+    UpToDate,
+    Migrated { from: u32, to: u32 },
+    NeedsRebuild,
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS
+        .last()
+        .map(|migration| migration.version)
+        .unwrap_or(INITIAL_VERSION_NUMBER)
+}
+
+pub async fn create_migrations_tables(connection: &mut SqliteConnection) -> Result<(), SqlError> {
     trace!("Creating migrations tables");
 
     query(
@@ -20,24 +181,262 @@ pub async fn create_migrations_tables(connection: &mut SqliteConnection) -> Resu
     Ok(())
 }
 
-pub async fn is_db_up_to_date(connection: &mut SqliteConnection) -> Result<bool, Error> {
-    let result: Result<Option<u32>, Error> = query("SELECT version FROM migrations LIMIT 1")
+pub async fn get_db_version(connection: &mut SqliteConnection) -> Option<u32> {
+    let result: Result<Option<u32>, SqlError> = query("SELECT version FROM migrations LIMIT 1")
         .map(|row: SqliteRow| row.get(0))
         .fetch_optional(connection)
         .await;
 
-    match result {
-        Ok(Some(version)) => Ok(version == MIGRATION_VERSION_NUMBER),
-        Ok(None) => Ok(false),
-        Err(_) => Ok(false),
-    }
+    result.ok().flatten()
 }
 
-pub async fn set_db_version(connection: &mut SqliteConnection) -> Result<(), Error> {
-    query("INSERT INTO migrations (id, version) VALUES(0, ?)")
-        .bind(&MIGRATION_VERSION_NUMBER)
+pub async fn set_db_version(
+    connection: &mut SqliteConnection,
+    version: u32,
+) -> Result<(), SqlError> {
+    query("INSERT OR REPLACE INTO migrations (id, version) VALUES(0, ?)")
+        .bind(version)
         .execute(connection)
         .await?;
 
     Ok(())
 }
+
+pub async fn migrate_db(
+    connection: &mut SqliteConnection,
+    log: &OffsetLog<u32>,
+    keys: &[Keypair],
+    content_key: Option<&ContentKey>,
+    indexers: &[Arc<dyn Indexer>],
+) -> Result<MigrateOutcome, Error> {
+    let latest = latest_version();
+    let current = match get_db_version(connection).await {
+        Some(version) if version <= latest => version,
+        // no version means no db, a newer version means a db we don't know how to read
+        _ => return Ok(MigrateOutcome::NeedsRebuild),
+    };
+
+    if current == latest {
+        return Ok(MigrateOutcome::UpToDate);
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+        .collect();
+
+    if pending
+        .iter()
+        .any(|migration| matches!(migration.backfill, Backfill::Impossible))
+    {
+        return Ok(MigrateOutcome::NeedsRebuild);
+    }
+
+    // a db left at a version that still needs reindexing would never be reindexed, so it
+    // is all one transaction
+    let mut tx = connection.begin().await?;
+    let mut reindexed_names = Vec::new();
+    let mut is_decrypt_pending = false;
+    for migration in pending {
+        info!(
+            "migrating sqlite db to version {}: {}",
+            migration.version, migration.description
+        );
+
+        (migration.up)(&mut tx).await?;
+        match migration.backfill {
+            Backfill::Rust(backfill) => backfill(&mut tx, log, keys, content_key).await?,
+            Backfill::Reindex(names) => reindexed_names.extend_from_slice(names),
+            Backfill::Decrypt => is_decrypt_pending = true,
+            Backfill::None | Backfill::Impossible => {}
+        }
+        set_db_version(&mut tx, migration.version).await?;
+    }
+
+    let reindexed: Vec<Arc<dyn Indexer>> = indexers
+        .iter()
+        .filter(|indexer| reindexed_names.contains(&indexer.name()))
+        .cloned()
+        .collect();
+    if !reindexed.is_empty() {
+        info!(
+            "rebuilding indexes after migrating: {}",
+            reindexed_names.join(", ")
+        );
+        // the built-in indexers named by migrations can all be cleared
+        rebuild_indexers(&mut tx, log, content_key, &reindexed).await?;
+    }
+    // after reindexing, so the msgs it decrypts are only indexed once
+    if is_decrypt_pending {
+        let mut group_keys = select_group_keys(&mut tx).await?;
+        decrypt_indexed_msgs(&mut tx, log, indexers, content_key, keys, &mut group_keys).await?;
+    }
+    tx.commit().await?;
+
+    Ok(MigrateOutcome::Migrated {
+        from: current,
+        to: latest,
+    })
+}
+
+async fn create_about_feed_names_schema(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+    create_about_feed_names_tables(connection).await?;
+    create_about_feed_names_indices(connection).await
+}
+
+async fn create_hashtags_schema(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+    create_hashtags_tables(connection).await?;
+    create_hashtags_indices(connection).await
+}
+
+async fn create_chain_issues_schema(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+    create_chain_issues_tables(connection).await?;
+    create_chain_issues_indices(connection).await
+}
+
+// The content of msgs decrypted when they were indexed wasn't kept, so decrypt them again.
+fn backfill_decrypted_contents<'c>(
+    connection: &'c mut SqliteConnection,
+    log: &'c OffsetLog<u32>,
+    keys: &'c [Keypair],
+    content_key: Option<&'c ContentKey>,
+) -> BoxFuture<'c, Result<(), Error>> {
+    Box::pin(async move {
+        let mut after_log_seq = None;
+        loop {
+            let rows =
+                select_encrypted_msgs(connection, true, after_log_seq, BACKFILL_PAGE_SIZE).await?;
+            let Some((last_log_seq, _)) = rows.last() else {
+                break;
+            };
+            after_log_seq = Some(*last_log_seq);
+
+            for (log_seq, msg_ref_id) in rows {
+                let bytes = log.get(log_seq).map_err(Error::LogGet)?;
+                let msg: Msg<Value> = serde_json::from_slice(bytes.as_slice())?;
+                let dm_recps = select_dm_recps(connection, &msg, keys).await?;
+                if let (true, msg) = attempt_decryption(msg, keys, &[], &dm_recps) {
+                    let content = &msg.value.content;
+                    insert_decrypted_content(connection, content_key, msg_ref_id, content).await?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+async fn create_groups_schema(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+    create_groups_tables(connection).await?;
+    create_groups_indices(connection).await
+}
+
+async fn create_private_conversations_schema(
+    connection: &mut SqliteConnection,
+) -> Result<(), SqlError> {
+    create_private_conversations_tables(connection).await?;
+    create_private_conversations_indices(connection).await
+}
+
+async fn create_private_conversations_summary_schema(
+    connection: &mut SqliteConnection,
+) -> Result<(), SqlError> {
+    add_private_conversations_summary_columns(connection).await?;
+    create_private_conversations_indices(connection).await
+}
+
+// The recipients are read from the decrypted contents, so the offset log isn't needed.
+fn backfill_private_conversations<'c>(
+    connection: &'c mut SqliteConnection,
+    _log: &'c OffsetLog<u32>,
+    _keys: &'c [Keypair],
+    content_key: Option<&'c ContentKey>,
+) -> BoxFuture<'c, Result<(), Error>> {
+    Box::pin(async move {
+        insert_missing_private_conversation_msgs(connection, content_key).await?;
+        Ok(())
+    })
+}
+
+async fn create_threads_schema(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+    create_threads_tables(connection).await?;
+    create_threads_indices(connection).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use serde_json::json;
+    use ssb_msg::keypair_feed_ref;
+
+    // Drop what the migrations after version 8 added, as an indexer at that version would
+    // have left the db.
+    async fn downgrade_to_v8(connection: &mut SqliteConnection) {
+        for statement in [
+            "DROP TABLE group_members",
+            "DROP TABLE groups",
+            "DROP TABLE private_conversation_msgs",
+            "DROP TABLE private_conversations",
+            "DROP INDEX msgs_timestamp_received_index",
+            "DROP INDEX msgs_timestamp_asserted_index",
+            "DROP INDEX msgs_feed_seq_index",
+            "DROP TABLE thread_participants",
+            "DROP TABLE threads",
+            "ALTER TABLE about_feeds DROP COLUMN timestamp_received",
+        ] {
+            query(statement).execute(&mut *connection).await.unwrap();
+        }
+        set_db_version(connection, 8).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrates_v8_db_with_decryptable_msgs() {
+        let dir = temp_dir();
+        let (alice, me) = (keypair(1), keypair(2));
+        let me_feed = keypair_feed_ref(&me);
+        let content = json!({
+            "type": "post",
+            "text": "hi",
+            "recps": [keypair_feed_ref(&alice), me_feed],
+        });
+        let dm = append_dm(&dir, &alice, &me_feed, content);
+        // abouts are indexed into a table later migrations add columns to
+        let bob_feed = keypair_feed_ref(&keypair(3));
+        let about = json!({
+            "type": "about",
+            "about": bob_feed,
+            "name": "bob",
+            "recps": [bob_feed, me_feed],
+        });
+        append_dm(&dir, &keypair(3), &me_feed, about);
+
+        // indexed without keys, the dm is kept encrypted
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+        drop(db);
+        let mut connection = create_connection(sql_path(&dir)).await.unwrap();
+        downgrade_to_v8(&mut connection).await;
+        drop(connection);
+
+        let db = open_db(
+            &dir,
+            vec![private_keypair(&me)],
+            vec![Arc::new(PostTextIndexer)],
+        )
+        .await;
+        let reader = db.reader();
+        let msg = reader.get_msg(dm.key).await.unwrap().unwrap();
+        assert_eq!(msg.value.content["text"], "hi");
+        let profile = reader.get_profile(&bob_feed).await.unwrap().unwrap();
+        assert_eq!(profile.name.as_deref(), Some("bob"));
+        let conversations = reader.get_private_conversations(None, 10).await.unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert!(conversations
+            .iter()
+            .all(|conversation| conversation.msg_count == 1));
+        // custom indexers see the msgs decrypted by the migrations
+        let mut connection = reader.acquire().await.unwrap();
+        assert_eq!(select_post_texts(&mut connection).await, ["hi"]);
+    }
+}
@@ -1,47 +1,89 @@
 use flumedb::flume_view::Sequence;
 use log::trace;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::Value;
 use sqlx::{
     query,
-    sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteRow},
+    sqlite::{
+        SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+        SqliteRow,
+    },
     ConnectOptions, Error as SqlError, Row,
 };
-use ssb_msg::{BlobLink, Link, Msg, MsgContent};
 use std::path::Path;
+use std::sync::Arc;
+
+use crate::indexer::{create_indexer_schema, Indexer};
 
 mod abouts;
 mod blob_links;
 mod blob_refs;
+mod chain_issues;
 mod contacts;
+mod decrypted_contents;
 mod feed_links;
 mod feed_refs;
+mod graph;
+mod groups;
+mod hashtags;
+mod indexers;
+mod mentions;
 mod migrations;
 mod msg_links;
+mod msg_query;
 mod msg_refs;
 mod msgs;
 mod post_branches;
 mod posts;
+mod private_conversations;
 mod queries;
+mod search;
+mod threads;
+mod timeline;
 mod votes;
-use self::abouts::*;
+pub(crate) use self::abouts::*;
 use self::blob_links::*;
 use self::blob_refs::*;
+pub(crate) use self::chain_issues::*;
 use self::contacts::*;
+pub(crate) use self::decrypted_contents::*;
+pub use self::decrypted_contents::{content_key_from_secret, ContentKey};
 use self::feed_links::*;
+pub(crate) use self::feed_refs::find_feed_ref;
 use self::feed_refs::*;
-pub(crate) use self::migrations::is_db_up_to_date;
+pub use self::graph::FeedHops;
+pub(crate) use self::graph::*;
+pub use self::groups::Group;
+pub(crate) use self::groups::*;
+pub(crate) use self::hashtags::*;
+pub use self::hashtags::{HashtagCount, SelectMsgsByHashtagOptions};
+pub(crate) use self::indexers::*;
+pub(crate) use self::mentions::*;
+pub use self::mentions::{MentionKind, ReceivedCursor};
 use self::migrations::*;
+pub(crate) use self::migrations::{migrate_db, MigrateOutcome};
 use self::msg_links::*;
-pub(crate) use self::msg_refs::find_or_create_msg_ref;
+pub(crate) use self::msg_query::*;
 use self::msg_refs::*;
+pub(crate) use self::msg_refs::{decode_msg_ref, find_msg_ref, find_or_create_msg_ref};
 use self::msgs::*;
-pub(crate) use self::msgs::{get_msg_log_seq, insert_msg};
+pub(crate) use self::msgs::{
+    get_msg_log_seq, insert_msg, select_encrypted_msgs, select_readable_msgs, update_msg_decrypted,
+};
 use self::post_branches::*;
+pub(crate) use self::posts::select_reply_counts;
 use self::posts::*;
-pub use self::queries::SelectAllMsgsByFeedOptions;
+pub use self::private_conversations::PrivateConversation;
+pub(crate) use self::private_conversations::*;
 pub(crate) use self::queries::*;
-use self::votes::*;
+pub use self::queries::{SelectAllMsgsByFeedOptions, Stats};
+pub use self::search::SearchOptions;
+pub(crate) use self::search::*;
+pub(crate) use self::threads::*;
+pub use self::threads::{ThreadCursor, ThreadSummary};
+pub use self::timeline::TimelineOptions;
+pub(crate) use self::timeline::*;
+pub(crate) use self::votes::*;
+pub use self::votes::{ExpressionCount, MsgVote};
 
 pub async fn create_connection<P: AsRef<Path>>(path: P) -> Result<SqliteConnection, SqlError> {
     SqliteConnectOptions::new()
@@ -52,11 +94,29 @@ pub async fn create_connection<P: AsRef<Path>>(path: P) -> Result<SqliteConnecti
         .await
 }
 
-pub async fn setup_new_db(connection: &mut SqliteConnection) -> Result<(), SqlError> {
+// Read-only connections that can query while the writer connection is indexing, as the db
+// is in WAL mode.
+pub async fn create_read_pool<P: AsRef<Path>>(path: P) -> Result<SqlitePool, SqlError> {
+    SqlitePoolOptions::new()
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .journal_mode(SqliteJournalMode::Wal)
+                .read_only(true),
+        )
+        .await
+}
+
+// Create the tables of ssb-db and of the indexers, which must include the built-in ones.
+pub(crate) async fn setup_new_db(
+    connection: &mut SqliteConnection,
+    indexers: &[Arc<dyn Indexer>],
+) -> Result<(), SqlError> {
     create_tables(connection).await?;
     create_indices(connection).await?;
+    create_indexer_schema(connection, indexers).await?;
 
-    set_db_version(connection).await?;
+    set_db_version(connection, latest_version()).await?;
 
     Ok(())
 }
@@ -80,59 +140,11 @@ pub async fn check_db_integrity(connection: &mut SqliteConnection) -> Result<boo
     }
 }
 
-pub async fn insert_content(
-    connection: &mut SqliteConnection,
-    msg: &Msg<Value>,
-    content: &MsgContent,
-    msg_ref_id: i64,
-    is_decrypted: bool,
-) -> Result<(), SqlError> {
-    match content {
-        MsgContent::Post(post) => {
-            if let Some(links) = &post.mentions {
-                let mut msg_refs = Vec::new();
-                let mut feed_refs = Vec::new();
-                let mut blob_refs = Vec::new();
-                let mut hashtag_keys = Vec::new();
-                for link in links.iter() {
-                    match link {
-                        Link::Msg { link, .. } => msg_refs.push(link),
-                        Link::Feed { link, .. } => feed_refs.push(link),
-                        Link::Blob(BlobLink { link, .. }) => blob_refs.push(link),
-                        Link::Hashtag { link } => hashtag_keys.push(link),
-                    }
-                }
-                insert_links(connection, msg_refs.as_slice(), msg_ref_id).await?;
-                insert_feed_links(connection, feed_refs.as_slice(), msg_ref_id).await?;
-                insert_blob_links(connection, blob_refs.as_slice(), msg_ref_id).await?;
-            }
-
-            insert_post(connection, &msg, &post, msg_ref_id).await?;
-            if let Some(branch) = &post.branch {
-                insert_post_branches(connection, branch.as_slice(), msg_ref_id).await?;
-            }
-        }
-        MsgContent::Contact(contact) => {
-            insert_or_update_contacts(connection, &msg, &contact, msg_ref_id, is_decrypted).await?;
-        }
-        MsgContent::Vote(vote) => {
-            insert_or_update_votes(connection, &msg, &vote).await?;
-        }
-        MsgContent::About(about) => {
-            insert_abouts(connection, &msg, &about).await?;
-        }
-        MsgContent::Unknown => {
-            // println!("Unknown content: {:?}", msg.value.content);
-        }
-    }
-
-    Ok(())
-}
-
 pub async fn get_latest(connection: &mut SqliteConnection) -> Result<Option<Sequence>, SqlError> {
+    // MAX is null when there are no msgs
     let res: Option<i64> = query("SELECT MAX(log_seq) FROM msgs")
         .map(|row: SqliteRow| row.get(0))
-        .fetch_optional(connection)
+        .fetch_one(connection)
         .await?;
 
     trace!("got latest seq from db: {:?}", res);
@@ -159,11 +171,10 @@ async fn create_tables(connection: &mut SqliteConnection) -> Result<(), SqlError
     create_feed_links_tables(connection).await?;
     create_blob_refs_tables(connection).await?;
     create_blob_links_tables(connection).await?;
-    create_contacts_tables(connection).await?;
-    create_abouts_tables(connection).await?;
-    create_votes_tables(connection).await?;
-    create_posts_tables(connection).await?;
-    create_post_branches_tables(connection).await?;
+    create_search_tables(connection).await?;
+    create_chain_issues_tables(connection).await?;
+    create_decrypted_contents_tables(connection).await?;
+    create_private_conversations_tables(connection).await?;
 
     Ok(())
 }
@@ -175,11 +186,8 @@ async fn create_indices(connection: &mut SqliteConnection) -> Result<(), SqlErro
     create_feed_refs_indices(connection).await?;
     create_feed_links_indices(connection).await?;
     create_blob_links_indices(connection).await?;
-    create_contacts_indices(connection).await?;
-    create_abouts_indices(connection).await?;
-    create_votes_indices(connection).await?;
-    create_posts_indices(connection).await?;
-    create_post_branches_indices(connection).await?;
+    create_chain_issues_indices(connection).await?;
+    create_private_conversations_indices(connection).await?;
     Ok(())
 }
 
@@ -0,0 +1,284 @@
+use sqlx::{sqlite::SqliteRow, Error, QueryBuilder, Row, Sqlite, SqliteConnection};
+
+use crate::query::{MsgCursor, MsgOrder, MsgQuery, QueryPage};
+use crate::sql::*;
+
+// The cursors of a page of msgs matching the query, in the order of the query.
+//
+// A page before a cursor is selected in the opposite order and reversed, so it ends next to
+// the cursor.
+pub async fn select_msg_query(
+    connection: &mut SqliteConnection,
+    msg_query: &MsgQuery,
+) -> Result<Vec<MsgCursor>, Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "
+        SELECT msgs.log_seq, msgs.timestamp_asserted, msgs.timestamp_received
+        FROM msgs
+        JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
+        WHERE 1
+        ",
+    );
+
+    if !msg_query.authors.is_empty() {
+        builder.push(" AND feed_refs.feed_ref IN (");
+        let mut authors = builder.separated(", ");
+        for author in &msg_query.authors {
+            authors.push_bind(Into::<String>::into(author));
+        }
+        builder.push(")");
+    }
+    if !msg_query.content_types.is_empty() {
+        builder.push(" AND msgs.content_type IN (");
+        let mut content_types = builder.separated(", ");
+        for content_type in &msg_query.content_types {
+            content_types.push_bind(content_type.clone());
+        }
+        builder.push(")");
+    }
+    if let Some(since) = msg_query.asserted_since {
+        builder
+            .push(" AND msgs.timestamp_asserted >= ")
+            .push_bind(since);
+    }
+    if let Some(until) = msg_query.asserted_until {
+        builder
+            .push(" AND msgs.timestamp_asserted < ")
+            .push_bind(until);
+    }
+    if let Some(since) = msg_query.received_since {
+        builder
+            .push(" AND msgs.timestamp_received >= ")
+            .push_bind(since);
+    }
+    if let Some(until) = msg_query.received_until {
+        builder
+            .push(" AND msgs.timestamp_received < ")
+            .push_bind(until);
+    }
+    if let Some(root) = &msg_query.root {
+        builder
+            .push(
+                " AND msgs.msg_ref_id IN (
+                    SELECT posts.msg_ref_id
+                    FROM posts
+                    JOIN msg_refs ON msg_refs.id = posts.root_msg_ref_id
+                    WHERE msg_refs.msg_ref = ",
+            )
+            .push_bind(Into::<String>::into(root))
+            .push(")");
+    }
+    if let Some(channel) = &msg_query.channel {
+        // the channel is stored with the search text of posts, normalised as a hashtag
+        match normalize_hashtag(channel) {
+            Some(channel) => {
+                builder
+                    .push(
+                        " AND EXISTS (
+                            SELECT 1 FROM search_msgs
+                            WHERE search_msgs.rowid = msgs.msg_ref_id AND search_msgs.channel = ",
+                    )
+                    .push_bind(channel)
+                    .push(")");
+            }
+            None => {
+                builder.push(" AND 0");
+            }
+        }
+    }
+    match msg_query.is_private {
+        Some(true) => {
+            builder.push(" AND msgs.is_decrypted = 1");
+        }
+        Some(false) => {
+            builder.push(" AND msgs.is_encrypted = 0");
+        }
+        None if !msg_query.is_undecryptable_included => {
+            builder.push(" AND (msgs.is_encrypted = 0 OR msgs.is_decrypted = 1)");
+        }
+        None => {}
+    }
+
+    let key = match msg_query.order {
+        MsgOrder::Log => None,
+        MsgOrder::Asserted => Some("msgs.timestamp_asserted"),
+        MsgOrder::Received => Some("msgs.timestamp_received"),
+    };
+    let is_newest_first = !msg_query.is_oldest_first;
+    let (cursor, is_descending) = match msg_query.page {
+        None => (None, is_newest_first),
+        Some(QueryPage::After(cursor)) => (Some(cursor), is_newest_first),
+        Some(QueryPage::Before(cursor)) => (Some(cursor), !is_newest_first),
+    };
+    let (comparison, direction) = if is_descending {
+        ("<", "DESC")
+    } else {
+        (">", "ASC")
+    };
+
+    if let Some(cursor) = cursor {
+        match key {
+            Some(key) => {
+                builder
+                    .push(format!(" AND ({}, msgs.log_seq) {} (", key, comparison))
+                    .push_bind(cursor.key)
+                    .push(", ")
+                    .push_bind(cursor.log_seq as i64)
+                    .push(")");
+            }
+            None => {
+                builder
+                    .push(format!(" AND msgs.log_seq {} ", comparison))
+                    .push_bind(cursor.log_seq as i64);
+            }
+        }
+    }
+    match key {
+        Some(key) => builder.push(format!(
+            " ORDER BY {} {}, msgs.log_seq {}",
+            key, direction, direction
+        )),
+        None => builder.push(format!(" ORDER BY msgs.log_seq {}", direction)),
+    };
+    builder.push(" LIMIT ").push_bind(msg_query.page_size);
+
+    let order = msg_query.order;
+    let mut cursors = builder
+        .build()
+        .map(|row: SqliteRow| {
+            let log_seq = row.get::<i64, _>(0) as Sequence;
+            let key = match order {
+                MsgOrder::Log => log_seq as f64,
+                MsgOrder::Asserted => row.get(1),
+                MsgOrder::Received => row.get(2),
+            };
+            MsgCursor {
+                order,
+                key,
+                log_seq,
+            }
+        })
+        .fetch_all(connection)
+        .await?;
+
+    if let Some(QueryPage::Before(_)) = msg_query.page {
+        cursors.reverse();
+    }
+    Ok(cursors)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use ssb_msg::keypair_feed_ref;
+
+    use crate::query::{MsgOrder, MsgQuery};
+    use crate::reader::MsgPage;
+    use crate::test_utils::*;
+    use crate::DatabaseReader;
+
+    const TEXTS: [&str; 5] = ["one", "two", "three", "four", "five"];
+
+    fn texts(page: &MsgPage) -> Vec<String> {
+        page.msgs
+            .iter()
+            .map(|msg| msg.value.content["text"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    // Page through every msg with `page_after`, then back to the first page with
+    // `page_before`, checking each page against the expected order.
+    async fn page_both_ways(reader: &DatabaseReader, msg_query: MsgQuery, expected: &[&str]) {
+        let mut pages = vec![reader.query_msgs(&msg_query).await.unwrap()];
+        while let Some(end) = pages.last().unwrap().end {
+            pages.push(
+                reader
+                    .query_msgs(&msg_query.clone().page_after(end))
+                    .await
+                    .unwrap(),
+            );
+        }
+        pages.pop();
+        let forward: Vec<String> = pages.iter().flat_map(texts).collect();
+        assert_eq!(forward, expected);
+
+        for index in (1..pages.len()).rev() {
+            let start = pages[index].start.unwrap();
+            let before = reader
+                .query_msgs(&msg_query.clone().page_before(start))
+                .await
+                .unwrap();
+            assert_eq!(texts(&before), texts(&pages[index - 1]));
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_both_ways_in_each_order() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        for text in TEXTS {
+            db.publish(&alice, &post(text)).await.unwrap();
+        }
+        let reader = db.reader();
+        let newest_first: Vec<&str> = TEXTS.into_iter().rev().collect();
+
+        for order in [MsgOrder::Log, MsgOrder::Asserted, MsgOrder::Received] {
+            let msg_query = MsgQuery::new().order_by(order).page_size(2);
+            page_both_ways(&reader, msg_query.clone(), &newest_first).await;
+            page_both_ways(&reader, msg_query.oldest_first(), &TEXTS).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_channels_as_hashtags() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let in_channel =
+            from_value(json!({ "type": "post", "text": "in channel", "channel": "#Rust" }))
+                .unwrap();
+        db.publish(&alice, &in_channel).await.unwrap();
+        db.publish(&alice, &post("elsewhere")).await.unwrap();
+        let reader = db.reader();
+
+        for channel in ["rust", "#RUST"] {
+            let page = reader
+                .query_msgs(&MsgQuery::new().channel(channel))
+                .await
+                .unwrap();
+            assert_eq!(texts(&page), ["in channel"]);
+        }
+        let page = reader
+            .query_msgs(&MsgQuery::new().channel("#"))
+            .await
+            .unwrap();
+        assert!(page.msgs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_out_undecryptable_msgs_unless_asked() {
+        let dir = temp_dir();
+        let (alice, bob) = (keypair(1), keypair(2));
+        append_dm(
+            &dir,
+            &alice,
+            &keypair_feed_ref(&bob),
+            json!({ "type": "post", "text": "secret" }),
+        );
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+        db.publish(&bob, &post("public")).await.unwrap();
+        let reader = db.reader();
+
+        let page = reader.query_msgs(&MsgQuery::new()).await.unwrap();
+        assert_eq!(texts(&page), ["public"]);
+
+        let page = reader
+            .query_msgs(&MsgQuery::new().include_undecryptable())
+            .await
+            .unwrap();
+        assert_eq!(page.msgs.len(), 2);
+        assert!(page.msgs[1].value.content.is_string());
+    }
+}
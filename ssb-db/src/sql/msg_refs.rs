@@ -2,15 +2,22 @@ use log::trace;
 use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
 use ssb_ref::MsgRef;
 
-pub async fn find_or_create_msg_ref(
+pub async fn find_msg_ref(
     connection: &mut SqliteConnection,
     msg_ref: &MsgRef,
-) -> Result<i64, Error> {
-    let result: Option<i64> = query("SELECT id FROM msg_refs WHERE msg_ref=?1")
+) -> Result<Option<i64>, Error> {
+    query("SELECT id FROM msg_refs WHERE msg_ref=?1")
         .bind(Into::<String>::into(msg_ref))
         .map(|row: SqliteRow| row.get(0))
-        .fetch_optional(&mut *connection)
-        .await?;
+        .fetch_optional(connection)
+        .await
+}
+
+pub async fn find_or_create_msg_ref(
+    connection: &mut SqliteConnection,
+    msg_ref: &MsgRef,
+) -> Result<i64, Error> {
+    let result = find_msg_ref(&mut *connection, msg_ref).await?;
 
     if let Some(found_msg_ref) = result {
         Ok(found_msg_ref)
@@ -24,6 +31,10 @@ pub async fn find_or_create_msg_ref(
     }
 }
 
+pub fn decode_msg_ref(msg_ref: String) -> Result<MsgRef, Error> {
+    MsgRef::from_string(msg_ref).map_err(|err| Error::Decode(Box::new(err)))
+}
+
 pub async fn create_msg_refs_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
     trace!("Creating msg_refs tables");
 
@@ -46,6 +46,80 @@ pub async fn insert_msg(
     Ok(())
 }
 
+// Mark an encrypted msg as decrypted, its content type was unknown when it was inserted.
+pub async fn update_msg_decrypted(
+    connection: &mut SqliteConnection,
+    log_seq: Sequence,
+    content_type: Option<&str>,
+) -> Result<(), Error> {
+    query("UPDATE msgs SET is_decrypted = 1, content_type = ? WHERE log_seq = ?")
+        .bind(content_type)
+        .bind(log_seq as i64)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+// Get the log_seq and msg_ref_id of encrypted msgs that the keys did or didn't decrypt.
+pub async fn select_encrypted_msgs(
+    connection: &mut SqliteConnection,
+    is_decrypted: bool,
+    after_log_seq: Option<Sequence>,
+    limit: i64,
+) -> Result<Vec<(Sequence, i64)>, Error> {
+    query(
+        "
+        SELECT log_seq, msg_ref_id
+        FROM msgs
+        WHERE
+            is_encrypted = 1
+            AND is_decrypted = ?1
+            AND log_seq > ?2
+        ORDER BY log_seq
+        LIMIT ?3
+        ",
+    )
+    .bind(is_decrypted)
+    .bind(after_log_seq.map(|log_seq| log_seq as i64).unwrap_or(-1))
+    .bind(limit)
+    .map(|row: SqliteRow| (row.get::<i64, _>(0) as Sequence, row.get(1)))
+    .fetch_all(connection)
+    .await
+}
+
+// Msgs with readable content after a log seq, with the decrypted content of private msgs.
+pub async fn select_readable_msgs(
+    connection: &mut SqliteConnection,
+    content_key: Option<&ContentKey>,
+    after_log_seq: Option<Sequence>,
+    limit: i64,
+) -> Result<Vec<(Sequence, i64, Option<Value>)>, Error> {
+    query(
+        "
+        SELECT msgs.log_seq, msgs.msg_ref_id, decrypted_contents.content
+        FROM msgs
+        LEFT JOIN decrypted_contents ON decrypted_contents.msg_ref_id = msgs.msg_ref_id
+        WHERE
+            (msgs.is_encrypted = 0 OR msgs.is_decrypted = 1)
+            AND msgs.log_seq > ?1
+        ORDER BY msgs.log_seq
+        LIMIT ?2
+        ",
+    )
+    .bind(after_log_seq.map(|log_seq| log_seq as i64).unwrap_or(-1))
+    .bind(limit)
+    .try_map(|row: SqliteRow| {
+        let content = match row.get::<Option<&str>, _>(2) {
+            Some(content) => open_decrypted_content(content_key, row.get(1), content)?,
+            None => None,
+        };
+        Ok((row.get::<i64, _>(0) as Sequence, row.get(1), content))
+    })
+    .fetch_all(connection)
+    .await
+}
+
 pub async fn get_msg_log_seq(
     connection: &mut SqliteConnection,
     msg_ref: &MsgRef,
@@ -103,6 +177,35 @@ pub async fn create_msgs_indices(connection: &mut SqliteConnection) -> Result<()
 
     create_content_type_index(connection).await?;
     create_feed_ref_index(connection).await?;
+    create_msgs_timestamp_indices(connection).await?;
+    create_msgs_feed_seq_index(connection).await?;
+
+    Ok(())
+}
+
+// For finding the msg of a feed at a sequence, which is how votes reference their msg
+pub async fn create_msgs_feed_seq_index(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating msgs feed_seq index");
+    query("CREATE INDEX IF NOT EXISTS msgs_feed_seq_index on msgs (feed_ref_id, feed_seq)")
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+// For paging by time, ties are broken by log_seq
+pub async fn create_msgs_timestamp_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating msgs timestamp indices");
+    query(
+        "CREATE INDEX IF NOT EXISTS msgs_timestamp_received_index on msgs (timestamp_received, log_seq)",
+    )
+    .execute(&mut *connection)
+    .await?;
+    query(
+        "CREATE INDEX IF NOT EXISTS msgs_timestamp_asserted_index on msgs (timestamp_asserted, log_seq)",
+    )
+    .execute(connection)
+    .await?;
 
     Ok(())
 }
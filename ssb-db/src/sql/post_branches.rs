@@ -47,8 +47,14 @@ pub async fn create_post_branches_tables(connection: &mut SqliteConnection) -> R
     Ok(())
 }
 
-pub async fn create_post_branches_indices(_connection: &mut SqliteConnection) -> Result<(), Error> {
+pub async fn create_post_branches_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
     trace!("Creating post_branches indices");
 
+    query(
+        "CREATE INDEX IF NOT EXISTS post_branches_from_to_index on post_branches (link_from_msg_ref_id, link_to_msg_ref_id)",
+    )
+    .execute(connection)
+    .await?;
+
     Ok(())
 }
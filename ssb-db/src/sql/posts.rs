@@ -1,15 +1,17 @@
 use log::trace;
 use serde_json::Value;
-use sqlx::{query, Error, SqliteConnection};
+use sqlx::{query, sqlite::SqliteRow, Error, QueryBuilder, Row, Sqlite, SqliteConnection};
 use ssb_msg::{Msg, PostContent};
+use ssb_ref::MsgRef;
 
 use crate::sql::*;
 
 pub async fn insert_post(
     connection: &mut SqliteConnection,
-    _msg: &Msg<Value>,
+    msg: &Msg<Value>,
     post: &PostContent,
     msg_ref_id: i64,
+    is_decrypted: bool,
 ) -> Result<(), Error> {
     let root_msg_ref_id = if let Some(root) = post.root.clone() {
         trace!("find or create root key id");
@@ -35,9 +37,22 @@ pub async fn insert_post(
     .bind(msg_ref_id)
     .bind(root_msg_ref_id)
     .bind(fork_msg_ref_id)
-    .execute(connection)
+    .execute(&mut *connection)
     .await?;
 
+    // private threads are left out of the thread summaries
+    if !is_decrypted {
+        let feed_ref_id = find_or_create_feed_ref(&mut *connection, &msg.value.author).await?;
+        insert_thread_post(
+            connection,
+            root_msg_ref_id.unwrap_or(msg_ref_id),
+            root_msg_ref_id.is_some(),
+            feed_ref_id,
+            msg.timestamp_received,
+        )
+        .await?;
+    }
+
     Ok(())
 }
 
@@ -102,3 +117,33 @@ async fn create_fork_index(connection: &mut SqliteConnection) -> Result<(), Erro
 
     Ok(())
 }
+
+// Reply counts of the msgs that are the root of any posts
+pub async fn select_reply_counts(
+    connection: &mut SqliteConnection,
+    msg_refs: &[MsgRef],
+) -> Result<Vec<(String, i64)>, Error> {
+    if msg_refs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "
+        SELECT msg_refs.msg_ref, COUNT(*)
+        FROM posts
+        JOIN msg_refs ON msg_refs.id = posts.root_msg_ref_id
+        WHERE
+            msg_refs.msg_ref IN (",
+    );
+    let mut roots = builder.separated(", ");
+    for msg_ref in msg_refs {
+        roots.push_bind(Into::<String>::into(msg_ref));
+    }
+    builder.push(") GROUP BY posts.root_msg_ref_id");
+
+    builder
+        .build()
+        .map(|row: SqliteRow| (row.get(0), row.get(1)))
+        .fetch_all(connection)
+        .await
+}
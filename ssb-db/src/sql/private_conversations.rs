@@ -0,0 +1,431 @@
+use log::trace;
+use serde_json::Value;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::FeedRef;
+
+use crate::sql::*;
+
+// Number of decrypted contents read at a time when filling conversations
+const PAGE_SIZE: i64 = 1_000;
+
+// Decrypted msgs are grouped into conversations by their recipients. The author is counted
+// as a recipient, so replies between the same feeds stay in one conversation. Each
+// conversation keeps its msg count and latest msg, updated as msgs are added to it.
+
+#[derive(Debug)]
+pub struct PrivateConversation {
+    pub id: i64,
+    // Sorted feed ids, or the cloaked ids of groups
+    pub recps: Vec<String>,
+    pub msg_count: i64,
+    // The latest msg of the conversation, pass it to get the next page of conversations
+    pub last_activity: ReceivedCursor,
+}
+
+pub async fn create_private_conversations_tables(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Creating private_conversations tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS private_conversations (
+            id INTEGER PRIMARY KEY,
+            recps TEXT UNIQUE NOT NULL,
+            msg_count INTEGER NOT NULL DEFAULT 0,
+            last_timestamp_received REAL,
+            last_log_seq INTEGER
+        )
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS private_conversation_msgs (
+            msg_ref_id INTEGER PRIMARY KEY,
+            conversation_id INTEGER NOT NULL,
+            FOREIGN KEY (msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (conversation_id)
+                REFERENCES private_conversations (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn create_private_conversations_indices(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Creating private_conversations indices");
+
+    query(
+        "CREATE INDEX IF NOT EXISTS private_conversation_msgs_conversation_id_index on private_conversation_msgs (conversation_id)",
+    )
+    .execute(&mut *connection)
+    .await?;
+    query(
+        "CREATE INDEX IF NOT EXISTS private_conversations_last_activity_index on private_conversations (last_timestamp_received, last_log_seq)",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// Add the summary columns to conversation tables created before they were kept, and fill
+// them from the msgs already in each conversation.
+pub async fn add_private_conversations_summary_columns(
+    connection: &mut SqliteConnection,
+) -> Result<(), Error> {
+    trace!("Adding private_conversations summary columns");
+
+    let has_columns: bool = query(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('private_conversations') WHERE name = 'msg_count'",
+    )
+    .map(|row: SqliteRow| row.get(0))
+    .fetch_one(&mut *connection)
+    .await?;
+    if has_columns {
+        return Ok(());
+    }
+
+    for statement in [
+        "ALTER TABLE private_conversations ADD COLUMN msg_count INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE private_conversations ADD COLUMN last_timestamp_received REAL",
+        "ALTER TABLE private_conversations ADD COLUMN last_log_seq INTEGER",
+    ] {
+        query(statement).execute(&mut *connection).await?;
+    }
+
+    query(
+        "
+        UPDATE private_conversations SET
+            msg_count = (
+                SELECT COUNT(*)
+                FROM private_conversation_msgs
+                WHERE private_conversation_msgs.conversation_id = private_conversations.id
+            ),
+            (last_timestamp_received, last_log_seq) = (
+                SELECT msgs.timestamp_received, msgs.log_seq
+                FROM private_conversation_msgs
+                JOIN msgs ON msgs.msg_ref_id = private_conversation_msgs.msg_ref_id
+                WHERE private_conversation_msgs.conversation_id = private_conversations.id
+                ORDER BY msgs.timestamp_received DESC, msgs.log_seq DESC
+                LIMIT 1
+            )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// `recps` are either ids or links with a name, e.g. `{ "link": "@...", "name": "alice" }`.
+fn collect_recps(author: &FeedRef, content: &Value) -> Vec<String> {
+    let mut recps: Vec<String> = content
+        .get("recps")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|recp| match recp {
+            Value::String(recp) => Some(recp.clone()),
+            recp => recp.get("link")?.as_str().map(str::to_string),
+        })
+        .chain([author.to_string()])
+        .collect();
+    recps.sort();
+    recps.dedup();
+    recps
+}
+
+async fn find_or_create_private_conversation(
+    connection: &mut SqliteConnection,
+    recps: &str,
+) -> Result<i64, Error> {
+    let result: Option<i64> = query("SELECT id FROM private_conversations WHERE recps = ?")
+        .bind(recps)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_optional(&mut *connection)
+        .await?;
+
+    if let Some(found_conversation) = result {
+        Ok(found_conversation)
+    } else {
+        let created_conversation = query("INSERT INTO private_conversations (recps) VALUES (?)")
+            .bind(recps)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(created_conversation.last_insert_rowid())
+    }
+}
+
+pub async fn insert_private_conversation_msg(
+    connection: &mut SqliteConnection,
+    msg: &ConversationMsgRow,
+) -> Result<(), Error> {
+    trace!("insert private conversation msg");
+    let recps = collect_recps(&msg.author, &msg.content).join(" ");
+    let conversation_id = find_or_create_private_conversation(&mut *connection, &recps).await?;
+    let inserted = query(
+        "INSERT OR IGNORE INTO private_conversation_msgs (msg_ref_id, conversation_id) VALUES (?, ?)",
+    )
+    .bind(msg.msg_ref_id)
+    .bind(conversation_id)
+    .execute(&mut *connection)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Ok(());
+    }
+
+    // msgs may be added out of order, e.g. when decrypted with a key added later
+    query(
+        "
+        UPDATE private_conversations SET
+            msg_count = msg_count + 1,
+            last_timestamp_received = CASE
+                WHEN last_log_seq IS NULL OR (?2, ?3) > (last_timestamp_received, last_log_seq)
+                THEN ?2
+                ELSE last_timestamp_received
+            END,
+            last_log_seq = CASE
+                WHEN last_log_seq IS NULL OR (?2, ?3) > (last_timestamp_received, last_log_seq)
+                THEN ?3
+                ELSE last_log_seq
+            END
+        WHERE id = ?1
+        ",
+    )
+    .bind(conversation_id)
+    .bind(msg.timestamp_received)
+    .bind(msg.log_seq as i64)
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// A decrypted msg to add to its conversation
+pub struct ConversationMsgRow {
+    pub msg_ref_id: i64,
+    pub log_seq: Sequence,
+    pub timestamp_received: f64,
+    pub author: FeedRef,
+    pub content: Value,
+}
+
+// Add the decrypted msgs that aren't in a conversation yet to their conversations.
+//
+// The recipients are read from the decrypted contents, so this also fills the conversations
+// of msgs that were decrypted before the conversation tables existed. Contents the content
+// key can't open are left out.
+pub async fn insert_missing_private_conversation_msgs(
+    connection: &mut SqliteConnection,
+    content_key: Option<&ContentKey>,
+) -> Result<(), Error> {
+    let mut after_msg_ref_id = -1;
+    loop {
+        let page = select_decrypted_contents_without_conversation(
+            &mut *connection,
+            content_key,
+            after_msg_ref_id,
+            PAGE_SIZE,
+        )
+        .await?;
+        let Some((last_msg_ref_id, _)) = page.last() else {
+            break;
+        };
+        after_msg_ref_id = *last_msg_ref_id;
+
+        for (_, msg) in page {
+            let Some(msg) = msg else {
+                continue;
+            };
+            insert_private_conversation_msg(&mut *connection, &msg).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// The next page of decrypted msgs not in a conversation, by msg_ref_id, with None for the
+// ones whose content the content key can't open.
+async fn select_decrypted_contents_without_conversation(
+    connection: &mut SqliteConnection,
+    content_key: Option<&ContentKey>,
+    after_msg_ref_id: i64,
+    limit: i64,
+) -> Result<Vec<(i64, Option<ConversationMsgRow>)>, Error> {
+    query(
+        "
+        SELECT
+          decrypted_contents.msg_ref_id,
+          msgs.log_seq,
+          msgs.timestamp_received,
+          feed_refs.feed_ref,
+          decrypted_contents.content
+        FROM decrypted_contents
+        JOIN msgs ON msgs.msg_ref_id = decrypted_contents.msg_ref_id
+        JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
+        LEFT JOIN private_conversation_msgs
+            ON private_conversation_msgs.msg_ref_id = decrypted_contents.msg_ref_id
+        WHERE
+            private_conversation_msgs.msg_ref_id IS NULL
+            AND decrypted_contents.msg_ref_id > ?1
+        ORDER BY decrypted_contents.msg_ref_id
+        LIMIT ?2
+        ",
+    )
+    .bind(after_msg_ref_id)
+    .bind(limit)
+    .try_map(|row: SqliteRow| {
+        let msg_ref_id = row.get(0);
+        let Some(content) = open_decrypted_content(content_key, msg_ref_id, row.get(4))? else {
+            return Ok((msg_ref_id, None));
+        };
+        let msg = ConversationMsgRow {
+            msg_ref_id,
+            log_seq: row.get::<i64, _>(1) as Sequence,
+            timestamp_received: row.get(2),
+            author: decode_feed_ref(row.get(3))?,
+            content,
+        };
+        Ok((msg_ref_id, Some(msg)))
+    })
+    .fetch_all(connection)
+    .await
+}
+
+// Conversations with their latest msg received before the cursor, most recently active first.
+pub async fn select_private_conversations(
+    connection: &mut SqliteConnection,
+    before: Option<ReceivedCursor>,
+    page_size: i64,
+) -> Result<Vec<PrivateConversation>, Error> {
+    query(
+        "
+        SELECT id, recps, msg_count, last_timestamp_received, last_log_seq
+        FROM private_conversations
+        WHERE
+            msg_count > 0
+            AND (?1 IS NULL OR (last_timestamp_received, last_log_seq) < (?1, ?2))
+        ORDER BY last_timestamp_received DESC, last_log_seq DESC
+        LIMIT ?3
+        ",
+    )
+    .bind(before.map(|cursor| cursor.timestamp_received))
+    .bind(before.map(|cursor| cursor.log_seq as i64))
+    .bind(page_size)
+    .map(|row: SqliteRow| PrivateConversation {
+        id: row.get(0),
+        recps: row
+            .get::<String, _>(1)
+            .split(' ')
+            .map(str::to_string)
+            .collect(),
+        msg_count: row.get(2),
+        last_activity: ReceivedCursor {
+            timestamp_received: row.get(3),
+            log_seq: row.get::<i64, _>(4) as Sequence,
+        },
+    })
+    .fetch_all(connection)
+    .await
+}
+
+// Msgs of a conversation received before the cursor, newest first.
+pub async fn select_private_conversation_msgs(
+    connection: &mut SqliteConnection,
+    conversation_id: i64,
+    before: Option<ReceivedCursor>,
+    page_size: i64,
+) -> Result<Vec<ReceivedCursor>, Error> {
+    query(
+        "
+        SELECT msgs.log_seq, msgs.timestamp_received
+        FROM private_conversation_msgs
+        JOIN msgs ON msgs.msg_ref_id = private_conversation_msgs.msg_ref_id
+        WHERE
+            private_conversation_msgs.conversation_id = ?1
+            AND (?2 IS NULL OR (msgs.timestamp_received, msgs.log_seq) < (?2, ?3))
+        ORDER BY msgs.timestamp_received DESC, msgs.log_seq DESC
+        LIMIT ?4
+        ",
+    )
+    .bind(conversation_id)
+    .bind(before.map(|cursor| cursor.timestamp_received))
+    .bind(before.map(|cursor| cursor.log_seq as i64))
+    .bind(page_size)
+    .map(|row: SqliteRow| ReceivedCursor {
+        timestamp_received: row.get(1),
+        log_seq: row.get::<i64, _>(0) as Sequence,
+    })
+    .fetch_all(connection)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use serde_json::json;
+    use ssb_msg::{keypair_feed_ref, MsgBuilder};
+
+    #[tokio::test]
+    async fn keeps_the_latest_msg_of_each_conversation() {
+        let dir = temp_dir();
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let mut msg_ref_ids = Vec::new();
+        for seed in 1..=3 {
+            let msg = MsgBuilder::new(&post("")).unwrap().sign(&keypair(seed));
+            msg_ref_ids.push(find_or_create_msg_ref(&mut db.sql, &msg.key).await.unwrap());
+        }
+        let (alice, bob) = (keypair_feed_ref(&keypair(1)), keypair_feed_ref(&keypair(2)));
+        let content = json!({ "type": "post", "text": "hi", "recps": [alice, bob] });
+        let row = |msg_ref_id, log_seq, timestamp_received, author: &FeedRef| ConversationMsgRow {
+            msg_ref_id,
+            log_seq,
+            timestamp_received,
+            author: author.clone(),
+            content: content.clone(),
+        };
+
+        // added out of order and twice, as when msgs are decrypted with a key added later
+        for msg in [
+            row(msg_ref_ids[0], 5, 2.0, &alice),
+            row(msg_ref_ids[1], 3, 1.0, &bob),
+            row(msg_ref_ids[0], 5, 2.0, &alice),
+            row(msg_ref_ids[2], 4, 1.5, &keypair_feed_ref(&keypair(3))),
+        ] {
+            insert_private_conversation_msg(&mut db.sql, &msg)
+                .await
+                .unwrap();
+        }
+
+        let conversations = select_private_conversations(&mut db.sql, None, 1)
+            .await
+            .unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].msg_count, 2);
+        assert_eq!(conversations[0].last_activity.log_seq, 5);
+
+        let before = Some(conversations[0].last_activity);
+        let conversations = select_private_conversations(&mut db.sql, before, 10)
+            .await
+            .unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].msg_count, 1);
+        assert_eq!(conversations[0].last_activity.log_seq, 4);
+    }
+}
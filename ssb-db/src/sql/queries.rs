@@ -1,6 +1,7 @@
 use crate::sql::*;
 use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
 use ssb_ref::{FeedRef, MsgRef};
+use std::collections::HashMap;
 
 pub async fn select_max_seq_by_feed<'a>(
     connection: &mut SqliteConnection,
@@ -25,9 +26,35 @@ pub async fn select_max_seq_by_feed<'a>(
     Ok(max_seq)
 }
 
+// The key and sequence of the latest msg of a feed
+pub async fn select_latest_msg_ref_by_feed(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+) -> Result<Option<(MsgRef, u64)>, Error> {
+    query(
+        "
+        SELECT
+          msg_refs.msg_ref,
+          msgs.feed_seq
+        FROM msgs
+        JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
+        JOIN msg_refs ON msg_refs.id = msgs.msg_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+        ORDER BY msgs.feed_seq DESC
+        LIMIT 1
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .try_map(|row: SqliteRow| Ok((decode_msg_ref(row.get(0))?, row.get::<i64, _>(1) as u64)))
+    .fetch_optional(connection)
+    .await
+}
+
+#[derive(Clone, Copy)]
 pub struct SelectAllMsgsByFeedOptions<'a> {
     pub feed_ref: &'a FeedRef,
-    pub content_type: &'a str,
+    pub content_type: Option<&'a str>,
     pub page_size: i64,
     pub less_than_feed_seq: i64,
     pub is_decrypted: bool,
@@ -36,6 +63,16 @@ pub struct SelectAllMsgsByFeedOptions<'a> {
 pub async fn select_all_msg_log_seqs_by_feed<'a>(
     connection: &mut SqliteConnection,
     options: SelectAllMsgsByFeedOptions<'a>,
+) -> Result<Vec<Sequence>, Error> {
+    select_all_msg_log_seqs_by_feed_before(connection, options, None).await
+}
+
+// Forks have several msgs at the same sequence, so pages continue from the last msg's
+// `less_than_feed_seq` and `before_log_seq`, which orders msgs at the same sequence.
+pub async fn select_all_msg_log_seqs_by_feed_before<'a>(
+    connection: &mut SqliteConnection,
+    options: SelectAllMsgsByFeedOptions<'a>,
+    before_log_seq: Option<Sequence>,
 ) -> Result<Vec<Sequence>, Error> {
     let msg_log_seqs = query(
         "
@@ -43,12 +80,12 @@ pub async fn select_all_msg_log_seqs_by_feed<'a>(
         FROM msgs
         JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
         WHERE
-            feed_refs.feed_ref = ?
-            AND content_type = ?
-            AND feed_seq < ?
-            AND is_decrypted = ?
-        ORDER BY feed_seq DESC
-        LIMIT ?
+            feed_refs.feed_ref = ?1
+            AND (?2 IS NULL OR content_type = ?2)
+            AND (feed_seq < ?3 OR (feed_seq = ?3 AND log_seq < ?6))
+            AND is_decrypted = ?4
+        ORDER BY feed_seq DESC, log_seq DESC
+        LIMIT ?5
         ",
     )
     .bind(Into::<String>::into(options.feed_ref))
@@ -56,12 +93,139 @@ pub async fn select_all_msg_log_seqs_by_feed<'a>(
     .bind(options.less_than_feed_seq)
     .bind(options.is_decrypted)
     .bind(options.page_size)
+    .bind(before_log_seq.map(|log_seq| log_seq as i64).unwrap_or(-1))
     .map(|row: SqliteRow| row.get::<i64, _>(0) as Sequence)
     .fetch_all(connection)
     .await?;
 
     Ok(msg_log_seqs)
 }
+pub struct ThreadPost {
+    pub msg_ref_id: i64,
+    pub log_seq: Sequence,
+    pub timestamp_asserted: f64,
+    pub branch_msg_ref_ids: Vec<i64>,
+}
+
+pub async fn select_thread_posts(
+    connection: &mut SqliteConnection,
+    root_msg_ref_id: i64,
+) -> Result<Vec<ThreadPost>, Error> {
+    let mut posts: Vec<ThreadPost> = query(
+        "
+        SELECT
+          posts.msg_ref_id,
+          msgs.log_seq,
+          msgs.timestamp_asserted
+        FROM posts
+        JOIN msgs ON msgs.msg_ref_id = posts.msg_ref_id
+        WHERE
+            posts.root_msg_ref_id = ?
+        ORDER BY msgs.log_seq
+        ",
+    )
+    .bind(root_msg_ref_id)
+    .map(|row: SqliteRow| ThreadPost {
+        msg_ref_id: row.get(0),
+        log_seq: row.get::<i64, _>(1) as Sequence,
+        timestamp_asserted: row.get(2),
+        branch_msg_ref_ids: Vec::new(),
+    })
+    .fetch_all(&mut *connection)
+    .await?;
+
+    let branches: Vec<(i64, i64)> = query(
+        "
+        SELECT
+          post_branches.link_from_msg_ref_id,
+          post_branches.link_to_msg_ref_id
+        FROM post_branches
+        JOIN posts ON posts.msg_ref_id = post_branches.link_from_msg_ref_id
+        WHERE
+            posts.root_msg_ref_id = ?
+        ",
+    )
+    .bind(root_msg_ref_id)
+    .map(|row: SqliteRow| (row.get(0), row.get(1)))
+    .fetch_all(connection)
+    .await?;
+
+    let mut branches_by_post: HashMap<i64, Vec<i64>> = HashMap::new();
+    for (from, to) in branches {
+        branches_by_post.entry(from).or_default().push(to);
+    }
+    for post in posts.iter_mut() {
+        if let Some(branch_msg_ref_ids) = branches_by_post.remove(&post.msg_ref_id) {
+            post.branch_msg_ref_ids = branch_msg_ref_ids;
+        }
+    }
+
+    Ok(posts)
+}
+
+// Posts that start a new thread forked from this one
+pub async fn select_thread_fork_log_seqs(
+    connection: &mut SqliteConnection,
+    root_msg_ref_id: i64,
+) -> Result<Vec<Sequence>, Error> {
+    let log_seqs = query(
+        "
+        SELECT msgs.log_seq
+        FROM posts
+        JOIN msgs ON msgs.msg_ref_id = posts.msg_ref_id
+        WHERE
+            posts.fork_msg_ref_id = ?1
+            AND (posts.root_msg_ref_id IS NULL OR posts.root_msg_ref_id != ?1)
+        ORDER BY msgs.timestamp_asserted
+        ",
+    )
+    .bind(root_msg_ref_id)
+    .map(|row: SqliteRow| row.get::<i64, _>(0) as Sequence)
+    .fetch_all(connection)
+    .await?;
+
+    Ok(log_seqs)
+}
+
+pub struct Stats {
+    pub msgs_count: i64,
+    pub feeds_count: i64,
+    pub content_type_counts: Vec<(String, i64)>,
+}
+
+pub async fn select_stats(connection: &mut SqliteConnection) -> Result<Stats, Error> {
+    let msgs_count: i64 = query("SELECT COUNT(*) FROM msgs")
+        .fetch_one(&mut *connection)
+        .await?
+        .get(0);
+
+    let feeds_count: i64 = query("SELECT COUNT(DISTINCT feed_ref_id) FROM msgs")
+        .fetch_one(&mut *connection)
+        .await?
+        .get(0);
+
+    let content_type_counts = query(
+        "
+        SELECT
+          content_type,
+          COUNT(*) AS count
+        FROM msgs
+        WHERE content_type IS NOT NULL
+        GROUP BY content_type
+        ORDER BY count DESC
+        ",
+    )
+    .map(|row: SqliteRow| (row.get(0), row.get(1)))
+    .fetch_all(connection)
+    .await?;
+
+    Ok(Stats {
+        msgs_count,
+        feeds_count,
+        content_type_counts,
+    })
+}
+
 // select all posts by a user
 //   - greater than seq
 //   - limit 10
@@ -210,37 +374,6 @@ pub async fn select_back_links_by_msg(
 }
 
 /*
-pub fn how_many_friends_follow_id() {}
-pub fn who_is_friends_with_id() {}
-pub fn who_does_id_follow_one_way() {}
-pub fn who_does_follows_id_one_way() {}
-
-pub fn friends_two_hops(connection: Connection) {
-    //"
-    //SELECT
-    //feed_ref as id
-    //FROM
-    //feed_refs
-    //WHERE feed_refs.id IN (
-    //SELECT
-    //contact_feed_ref_id
-    //FROM contacts
-    //WHERE feed_ref_id == 1 AND state == 1
-    //UNION
-    //SELECT
-    //friend_contacts.contact_feed_ref_id
-    //FROM contacts
-    //join contacts AS friend_contacts ON friend_contacts.feed_ref_id == contacts.contact_feed_ref_id
-    //WHERE contacts.feed_ref_id == 1
-    //AND contacts.state == 1
-    //AND friend_contacts.state == 1
-    //EXCEPT
-    //SELECT
-    //contact_feed_ref_id
-    //FROM contacts
-    //WHERE feed_ref_id == 1
-    //AND state == -1)"
-}
 #[cfg(test)]
 mod test {
     use crate::sql::queries::back_link_references;
@@ -0,0 +1,222 @@
+use log::trace;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_msg::{AboutContent, PostContent};
+use ssb_ref::FeedRef;
+
+use crate::sql::*;
+
+pub async fn create_search_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating search tables");
+
+    // rowid is the msg_ref_id of the indexed msg
+    query(
+        "
+        CREATE VIRTUAL TABLE IF NOT EXISTS search_msgs USING fts5(
+            text,
+            channel UNINDEXED,
+            tokenize = 'unicode61 remove_diacritics 2'
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_post_search(
+    connection: &mut SqliteConnection,
+    post: &PostContent,
+    msg_ref_id: i64,
+) -> Result<(), Error> {
+    insert_search(connection, &post.text, post.channel.as_deref(), msg_ref_id).await
+}
+
+pub async fn insert_about_search(
+    connection: &mut SqliteConnection,
+    about: &AboutContent,
+    msg_ref_id: i64,
+) -> Result<(), Error> {
+    let text = [about.name.as_deref(), about.description.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    if text.is_empty() {
+        return Ok(());
+    }
+
+    insert_search(connection, &text, None, msg_ref_id).await
+}
+
+async fn insert_search(
+    connection: &mut SqliteConnection,
+    text: &str,
+    channel: Option<&str>,
+    msg_ref_id: i64,
+) -> Result<(), Error> {
+    trace!("insert search text");
+    // normalised as a hashtag, as channels are matched the same way
+    let channel = channel.and_then(normalize_hashtag);
+    query("INSERT OR REPLACE INTO search_msgs (rowid, text, channel) VALUES (?, ?, ?)")
+        .bind(msg_ref_id)
+        .bind(text)
+        .bind(channel)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub struct SearchOptions<'a> {
+    pub query: &'a str,
+    pub author: Option<&'a FeedRef>,
+    // Normalised the same way as when indexing, so "#Rust" matches the channel "rust"
+    pub channel: Option<&'a str>,
+    // asserted timestamps, in milliseconds
+    pub since: Option<f64>,
+    pub until: Option<f64>,
+    pub page_size: i64,
+    pub offset: i64,
+    // Decrypted private msgs are left out, so their text can't show up in snippets, unless
+    // asked for
+    pub include_private: bool,
+}
+
+pub struct SearchMatch {
+    pub log_seq: Sequence,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+pub async fn select_search_matches<'a>(
+    connection: &mut SqliteConnection,
+    options: SearchOptions<'a>,
+) -> Result<Vec<SearchMatch>, Error> {
+    let channel = match options.channel.map(normalize_hashtag) {
+        // nothing is left of the channel, so no post can be in it
+        Some(None) => return Ok(Vec::new()),
+        Some(channel) => channel,
+        None => None,
+    };
+
+    let matches = query(
+        "
+        SELECT
+          msgs.log_seq,
+          snippet(search_msgs, 0, '**', '**', '…', 16),
+          search_msgs.rank
+        FROM search_msgs
+        JOIN msgs ON msgs.msg_ref_id = search_msgs.rowid
+        JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
+        WHERE
+            search_msgs MATCH ?1
+            AND (?2 IS NULL OR feed_refs.feed_ref = ?2)
+            AND (?3 IS NULL OR search_msgs.channel = ?3)
+            AND (?4 IS NULL OR msgs.timestamp_asserted >= ?4)
+            AND (?5 IS NULL OR msgs.timestamp_asserted < ?5)
+            AND (?8 OR msgs.is_decrypted = 0)
+        ORDER BY search_msgs.rank
+        LIMIT ?6
+        OFFSET ?7
+        ",
+    )
+    .bind(to_fts_query(options.query))
+    .bind(options.author.map(Into::<String>::into))
+    .bind(channel)
+    .bind(options.since)
+    .bind(options.until)
+    .bind(options.page_size)
+    .bind(options.offset)
+    .bind(options.include_private)
+    .map(|row: SqliteRow| SearchMatch {
+        log_seq: row.get::<i64, _>(0) as Sequence,
+        snippet: row.get(1),
+        rank: row.get(2),
+    })
+    .fetch_all(connection)
+    .await?;
+
+    Ok(matches)
+}
+
+// Quote each word so that user input is never parsed as fts5 query syntax.
+fn to_fts_query(input: &str) -> String {
+    input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use ssb_msg::keypair_feed_ref;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    fn search_options(include_private: bool) -> SearchOptions<'static> {
+        SearchOptions {
+            query: "hello",
+            author: None,
+            channel: None,
+            since: None,
+            until: None,
+            page_size: 10,
+            offset: 0,
+            include_private,
+        }
+    }
+
+    #[tokio::test]
+    async fn leaves_out_private_msgs_unless_asked() {
+        let dir = temp_dir();
+        let me = keypair(1);
+        let friend = keypair(2);
+        append_dm(
+            &dir,
+            &friend,
+            &keypair_feed_ref(&me),
+            json!({ "type": "post", "text": "hello in private" }),
+        );
+        let mut db = open_db(&dir, vec![private_keypair(&me)], Vec::new()).await;
+        db.publish(&me, &post("hello in public")).await.unwrap();
+        let mut sql = db.reader().acquire().await.unwrap();
+
+        let public = select_search_matches(&mut sql, search_options(false))
+            .await
+            .unwrap();
+        assert_eq!(public.len(), 1);
+        assert_eq!(public[0].snippet, "**hello** in public");
+
+        let all = select_search_matches(&mut sql, search_options(true))
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn matches_channels_as_hashtags() {
+        let dir = temp_dir();
+        let alice = keypair(1);
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let in_channel =
+            from_value(json!({ "type": "post", "text": "hello", "channel": "Rust" })).unwrap();
+        db.publish(&alice, &in_channel).await.unwrap();
+        db.publish(&alice, &post("hello elsewhere")).await.unwrap();
+        let mut sql = db.reader().acquire().await.unwrap();
+
+        for channel in ["rust", "#Rust"] {
+            let options = SearchOptions {
+                channel: Some(channel),
+                ..search_options(false)
+            };
+            let matches = select_search_matches(&mut sql, options).await.unwrap();
+            assert_eq!(matches.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use log::trace;
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::{FeedRef, MsgRef};
+
+use crate::sql::*;
+
+// A summary of each thread of public posts, updated as its root and replies are indexed.
+// Activity times are when posts were received, the root counts as activity too.
+
+#[derive(Debug)]
+pub struct ThreadSummary {
+    pub root: MsgRef,
+    pub reply_count: i64,
+    // The root author and the repliers, in the order they joined
+    pub participants: Vec<FeedRef>,
+    pub first_activity: f64,
+    pub last_activity: f64,
+    pub last_replier: Option<FeedRef>,
+    // Pass the cursor of the last thread to get the next page
+    pub cursor: ThreadCursor,
+}
+
+// Position in a list of threads, most recently active first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThreadCursor {
+    pub last_activity: f64,
+    pub root_msg_ref_id: i64,
+}
+
+pub async fn create_threads_tables(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating threads tables");
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS threads (
+            root_msg_ref_id INTEGER PRIMARY KEY,
+            reply_count INTEGER NOT NULL,
+            first_activity REAL NOT NULL,
+            last_activity REAL NOT NULL,
+            last_reply_activity REAL,
+            last_replier_feed_ref_id INTEGER,
+            FOREIGN KEY (root_msg_ref_id)
+                REFERENCES msg_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (last_replier_feed_ref_id)
+                REFERENCES feed_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    query(
+        "
+        CREATE TABLE IF NOT EXISTS thread_participants (
+            id INTEGER PRIMARY KEY,
+            root_msg_ref_id INTEGER NOT NULL,
+            feed_ref_id INTEGER NOT NULL,
+            FOREIGN KEY (root_msg_ref_id)
+                REFERENCES threads (root_msg_ref_id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT,
+            FOREIGN KEY (feed_ref_id)
+                REFERENCES feed_refs (id)
+                ON UPDATE RESTRICT
+                ON DELETE RESTRICT
+        )
+        ",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn create_threads_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Creating threads indices");
+
+    query("CREATE INDEX IF NOT EXISTS threads_last_activity_index on threads (last_activity)")
+        .execute(&mut *connection)
+        .await?;
+    query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS thread_participants_root_feed_index on thread_participants (root_msg_ref_id, feed_ref_id)",
+    )
+    .execute(&mut *connection)
+    .await?;
+    query(
+        "CREATE INDEX IF NOT EXISTS thread_participants_feed_ref_id_index on thread_participants (feed_ref_id)",
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+// Count a post as activity in the thread of `root_msg_ref_id`, which is its own id if it is
+// the root. Posts may be indexed in any order, e.g. a reply before its root, and more than
+// once, so the replies are counted from the posts table, which must already have the post.
+pub async fn insert_thread_post(
+    connection: &mut SqliteConnection,
+    root_msg_ref_id: i64,
+    is_reply: bool,
+    feed_ref_id: i64,
+    timestamp_received: f64,
+) -> Result<(), Error> {
+    trace!("update thread");
+    query(
+        "
+        INSERT INTO threads (
+            root_msg_ref_id,
+            reply_count,
+            first_activity,
+            last_activity,
+            last_reply_activity,
+            last_replier_feed_ref_id
+        ) VALUES (
+            ?1,
+            (
+                SELECT COUNT(*)
+                FROM posts
+                JOIN msgs ON msgs.msg_ref_id = posts.msg_ref_id
+                WHERE
+                    posts.root_msg_ref_id = ?1
+                    AND msgs.is_decrypted = 0
+            ),
+            ?2,
+            ?2,
+            ?3,
+            ?4
+        )
+        ON CONFLICT (root_msg_ref_id) DO UPDATE SET
+            reply_count = excluded.reply_count,
+            first_activity = MIN(first_activity, excluded.first_activity),
+            last_activity = MAX(last_activity, excluded.last_activity),
+            last_reply_activity = MAX(
+                COALESCE(last_reply_activity, excluded.last_reply_activity),
+                COALESCE(excluded.last_reply_activity, last_reply_activity)
+            ),
+            last_replier_feed_ref_id = CASE
+                WHEN excluded.last_reply_activity >= COALESCE(last_reply_activity, excluded.last_reply_activity)
+                THEN excluded.last_replier_feed_ref_id
+                ELSE last_replier_feed_ref_id
+            END
+        ",
+    )
+    .bind(root_msg_ref_id)
+    .bind(timestamp_received)
+    .bind(is_reply.then_some(timestamp_received))
+    .bind(is_reply.then_some(feed_ref_id))
+    .execute(&mut *connection)
+    .await?;
+
+    query("INSERT OR IGNORE INTO thread_participants (root_msg_ref_id, feed_ref_id) VALUES (?, ?)")
+        .bind(root_msg_ref_id)
+        .bind(feed_ref_id)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+// Threads with replies, last active before the cursor, most recently active first.
+pub async fn select_active_threads(
+    connection: &mut SqliteConnection,
+    before: Option<ThreadCursor>,
+    page_size: i64,
+) -> Result<Vec<ThreadSummary>, Error> {
+    select_threads(connection, None, before, page_size).await
+}
+
+// Threads with replies that the feed started or replied to, last active before the cursor,
+// most recently active first.
+pub async fn select_threads_by_participant(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+    before: Option<ThreadCursor>,
+    page_size: i64,
+) -> Result<Vec<ThreadSummary>, Error> {
+    select_threads(connection, Some(feed_ref), before, page_size).await
+}
+
+async fn select_threads(
+    connection: &mut SqliteConnection,
+    participant: Option<&FeedRef>,
+    before: Option<ThreadCursor>,
+    page_size: i64,
+) -> Result<Vec<ThreadSummary>, Error> {
+    query(
+        "
+        SELECT
+          msg_refs.msg_ref,
+          threads.reply_count,
+          (
+            SELECT GROUP_CONCAT(feed_ref, ' ')
+            FROM (
+                SELECT feed_refs.feed_ref
+                FROM thread_participants
+                JOIN feed_refs ON feed_refs.id = thread_participants.feed_ref_id
+                LEFT JOIN msgs AS root_msgs
+                    ON root_msgs.msg_ref_id = thread_participants.root_msg_ref_id
+                WHERE thread_participants.root_msg_ref_id = threads.root_msg_ref_id
+                -- the root author first, even when a reply was indexed before the root
+                ORDER BY
+                    thread_participants.feed_ref_id IS root_msgs.feed_ref_id DESC,
+                    thread_participants.id
+            )
+          ),
+          threads.first_activity,
+          threads.last_activity,
+          last_replier_feed_refs.feed_ref,
+          threads.root_msg_ref_id
+        FROM threads
+        JOIN msg_refs ON msg_refs.id = threads.root_msg_ref_id
+        LEFT JOIN feed_refs AS last_replier_feed_refs
+            ON last_replier_feed_refs.id = threads.last_replier_feed_ref_id
+        WHERE
+            threads.reply_count > 0
+            AND (?1 IS NULL OR threads.root_msg_ref_id IN (
+                SELECT thread_participants.root_msg_ref_id
+                FROM thread_participants
+                JOIN feed_refs ON feed_refs.id = thread_participants.feed_ref_id
+                WHERE feed_refs.feed_ref = ?1
+            ))
+            AND (?2 IS NULL OR (threads.last_activity, threads.root_msg_ref_id) < (?2, ?3))
+        ORDER BY threads.last_activity DESC, threads.root_msg_ref_id DESC
+        LIMIT ?4
+        ",
+    )
+    .bind(participant.map(Into::<String>::into))
+    .bind(before.map(|cursor| cursor.last_activity))
+    .bind(before.map(|cursor| cursor.root_msg_ref_id))
+    .bind(page_size)
+    .try_map(|row: SqliteRow| {
+        let participants: Option<String> = row.get(2);
+        Ok(ThreadSummary {
+            root: decode_msg_ref(row.get(0))?,
+            reply_count: row.get(1),
+            participants: participants
+                .iter()
+                .flat_map(|participants| participants.split(' '))
+                .map(|participant| decode_feed_ref(participant.to_string()))
+                .collect::<Result<_, _>>()?,
+            first_activity: row.get(3),
+            last_activity: row.get(4),
+            last_replier: row
+                .get::<Option<String>, _>(5)
+                .map(decode_feed_ref)
+                .transpose()?,
+            cursor: ThreadCursor {
+                last_activity: row.get(4),
+                root_msg_ref_id: row.get(6),
+            },
+        })
+    })
+    .fetch_all(connection)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use ssb_msg::{keypair_feed_ref, MsgBuilder};
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[tokio::test]
+    async fn summarizes_threads_whose_replies_are_indexed_before_the_root() {
+        let dir = temp_dir();
+        let (alice, bob) = (keypair(1), keypair(2));
+        let root = MsgBuilder::new(&post("root")).unwrap().sign(&alice);
+        let reply_content = from_value(json!({
+            "type": "post",
+            "text": "reply",
+            "root": root.key.to_string(),
+            "branch": [root.key.to_string()],
+        }))
+        .unwrap();
+        let reply = MsgBuilder::new(&reply_content).unwrap().sign(&bob);
+        append_to_log(&dir, &reply);
+        append_to_log(&dir, &root);
+
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+        let mut connection = db.reader().acquire().await.unwrap();
+        let threads = select_active_threads(&mut connection, None, 10)
+            .await
+            .unwrap();
+
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].root.to_string(), root.key.to_string());
+        assert_eq!(threads[0].reply_count, 1);
+        let feed_id = |feed_ref: &FeedRef| Into::<String>::into(feed_ref);
+        assert_eq!(
+            threads[0].last_replier.as_ref().map(feed_id),
+            Some(feed_id(&keypair_feed_ref(&bob)))
+        );
+        let participants: Vec<String> = threads[0].participants.iter().map(feed_id).collect();
+        assert_eq!(
+            participants,
+            [
+                feed_id(&keypair_feed_ref(&alice)),
+                feed_id(&keypair_feed_ref(&bob))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_replies_indexed_again_once() {
+        let dir = temp_dir();
+        let (alice, bob) = (keypair(1), keypair(2));
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        let root = db.publish(&alice, &post("root")).await.unwrap();
+        let reply_content = from_value(json!({
+            "type": "post",
+            "text": "reply",
+            "root": root.key.to_string(),
+        }))
+        .unwrap();
+        let reply = db.publish(&bob, &reply_content).await.unwrap();
+
+        let root_msg_ref_id = find_or_create_msg_ref(&mut db.sql, &root.key)
+            .await
+            .unwrap();
+        let bob_feed_ref_id = find_or_create_feed_ref(&mut db.sql, &keypair_feed_ref(&bob))
+            .await
+            .unwrap();
+        insert_thread_post(
+            &mut db.sql,
+            root_msg_ref_id,
+            true,
+            bob_feed_ref_id,
+            reply.timestamp_received,
+        )
+        .await
+        .unwrap();
+
+        let threads = select_active_threads(&mut db.sql, None, 10).await.unwrap();
+        assert_eq!(threads[0].reply_count, 1);
+    }
+
+    #[tokio::test]
+    async fn pages_through_threads_active_at_the_same_time() {
+        let dir = temp_dir();
+        for seed in 2..5 {
+            let root = MsgBuilder::new(&post("root")).unwrap().sign(&keypair(seed));
+            let reply_content = from_value(json!({
+                "type": "post",
+                "text": "reply",
+                "root": root.key.to_string(),
+            }))
+            .unwrap();
+            let mut reply = MsgBuilder::new(&reply_content).unwrap().sign(&keypair(1));
+            reply.timestamp_received = 1.0;
+            append_to_log(&dir, &reply);
+        }
+        let mut db = open_db(&dir, Vec::new(), Vec::new()).await;
+        db.process(u64::MAX).await.unwrap();
+
+        let mut connection = db.reader().acquire().await.unwrap();
+        let first_page = select_active_threads(&mut connection, None, 2)
+            .await
+            .unwrap();
+        let before = first_page.last().map(|thread| thread.cursor);
+        let second_page = select_active_threads(&mut connection, before, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(second_page.len(), 1);
+    }
+}
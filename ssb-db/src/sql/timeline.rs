@@ -0,0 +1,53 @@
+use sqlx::{query, sqlite::SqliteRow, Error, Row, SqliteConnection};
+use ssb_ref::FeedRef;
+
+use crate::sql::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimelineOptions {
+    // Follows away from the feed, its own posts are at 0 hops
+    pub max_hops: u32,
+    // Include replies, not only the root posts of threads
+    pub include_replies: bool,
+    pub before: Option<ReceivedCursor>,
+    pub page_size: i64,
+}
+
+// Public posts by the feeds within max_hops of a feed, received before the cursor, newest
+// first.
+//
+// Feeds blocked by the feed are left out, and are not followed through.
+pub async fn select_timeline(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+    options: TimelineOptions,
+) -> Result<Vec<ReceivedCursor>, Error> {
+    query(&format!(
+        "
+        {}
+        SELECT msgs.log_seq, msgs.timestamp_received
+        FROM msgs
+        JOIN posts ON posts.msg_ref_id = msgs.msg_ref_id
+        WHERE
+            msgs.feed_ref_id IN (SELECT feed_ref_id FROM hops)
+            AND msgs.is_encrypted = 0
+            AND (?3 OR posts.root_msg_ref_id IS NULL)
+            AND (?4 IS NULL OR (msgs.timestamp_received, msgs.log_seq) < (?4, ?5))
+        ORDER BY msgs.timestamp_received DESC, msgs.log_seq DESC
+        LIMIT ?6
+        ",
+        HOPS_CTE
+    ))
+    .bind(Into::<String>::into(feed_ref))
+    .bind(options.max_hops)
+    .bind(options.include_replies)
+    .bind(options.before.map(|cursor| cursor.timestamp_received))
+    .bind(options.before.map(|cursor| cursor.log_seq as i64))
+    .bind(options.page_size)
+    .map(|row: SqliteRow| ReceivedCursor {
+        timestamp_received: row.get(1),
+        log_seq: row.get::<i64, _>(0) as Sequence,
+    })
+    .fetch_all(connection)
+    .await
+}
@@ -1,7 +1,8 @@
 use log::trace;
 use serde_json::Value;
-use sqlx::{query, Error, SqliteConnection};
-use ssb_msg::VoteContent;
+use sqlx::{query, sqlite::SqliteRow, Error, QueryBuilder, Row, Sqlite, SqliteConnection};
+use ssb_msg::{Msg, VoteContent};
+use ssb_ref::{FeedRef, MsgRef};
 
 use crate::sql::*;
 
@@ -16,6 +17,7 @@ pub async fn create_votes_tables(connection: &mut SqliteConnection) -> Result<()
             link_from_feed_ref_id INTEGER NOT NULL,
             link_to_msg_ref_id INTEGER NOT NULL,
             value INTEGER NOT NULL,
+            expression TEXT,
             FOREIGN KEY (link_from_feed_ref_id)
                 REFERENCES feed_refs (id)
                 ON UPDATE RESTRICT
@@ -52,20 +54,23 @@ pub async fn insert_or_update_votes(
 
     if let Some((id, feed_seq)) = row {
         if feed_seq < msg.value.sequence as i64 {
-            query("UPDATE votes SET value = ? WHERE id = ?")
+            query("UPDATE votes SET feed_seq = ?, value = ?, expression = ? WHERE id = ?")
+                .bind(msg.value.sequence as i64)
                 .bind(content.vote.value)
+                .bind(content.vote.expression.as_deref())
                 .bind(id)
                 .execute(connection)
                 .await?;
         }
     } else {
         query(
-            "INSERT INTO votes (feed_seq, link_from_feed_ref_id, link_to_msg_ref_id, value) VALUES (?, ?, ?, ?)",
+            "INSERT INTO votes (feed_seq, link_from_feed_ref_id, link_to_msg_ref_id, value, expression) VALUES (?, ?, ?, ?, ?)",
         )
         .bind(msg.value.sequence as i64)
         .bind(&link_from_feed_ref_id)
         .bind(&link_to_msg_ref_id)
         .bind(content.vote.value)
+        .bind(content.vote.expression.as_deref())
         .execute(connection)
         .await?;
     }
@@ -73,6 +78,178 @@ pub async fn insert_or_update_votes(
     Ok(())
 }
 
+pub async fn add_votes_expression_column(connection: &mut SqliteConnection) -> Result<(), Error> {
+    trace!("Adding votes expression column");
+
+    query("ALTER TABLE votes ADD COLUMN expression TEXT")
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+// Only the latest vote of each feed on a msg is kept, and only positive values count
+pub async fn select_vote_count(
+    connection: &mut SqliteConnection,
+    msg_ref: &MsgRef,
+) -> Result<i64, Error> {
+    query(
+        "
+        SELECT COUNT(*)
+        FROM votes
+        JOIN msg_refs ON msg_refs.id = votes.link_to_msg_ref_id
+        WHERE
+            msg_refs.msg_ref = ?
+            AND votes.value > 0
+        ",
+    )
+    .bind(Into::<String>::into(msg_ref))
+    .map(|row: SqliteRow| row.get(0))
+    .fetch_one(connection)
+    .await
+}
+
+// Vote counts of the msgs that have any votes
+pub async fn select_vote_counts(
+    connection: &mut SqliteConnection,
+    msg_refs: &[MsgRef],
+) -> Result<Vec<(String, i64)>, Error> {
+    if msg_refs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "
+        SELECT msg_refs.msg_ref, COUNT(*)
+        FROM votes
+        JOIN msg_refs ON msg_refs.id = votes.link_to_msg_ref_id
+        WHERE
+            votes.value > 0
+            AND msg_refs.msg_ref IN (",
+    );
+    let mut voted = builder.separated(", ");
+    for msg_ref in msg_refs {
+        voted.push_bind(Into::<String>::into(msg_ref));
+    }
+    builder.push(") GROUP BY votes.link_to_msg_ref_id");
+
+    builder
+        .build()
+        .map(|row: SqliteRow| (row.get(0), row.get(1)))
+        .fetch_all(connection)
+        .await
+}
+
+#[derive(Debug)]
+pub struct MsgVote {
+    pub feed_ref: FeedRef,
+    pub expression: Option<String>,
+    pub feed_seq: u64,
+}
+
+pub async fn select_votes_by_msg(
+    connection: &mut SqliteConnection,
+    msg_ref: &MsgRef,
+) -> Result<Vec<MsgVote>, Error> {
+    query(
+        "
+        SELECT
+          feed_refs.feed_ref,
+          votes.expression,
+          votes.feed_seq
+        FROM votes
+        JOIN msg_refs ON msg_refs.id = votes.link_to_msg_ref_id
+        JOIN feed_refs ON feed_refs.id = votes.link_from_feed_ref_id
+        WHERE
+            msg_refs.msg_ref = ?
+            AND votes.value > 0
+        ORDER BY votes.id
+        ",
+    )
+    .bind(Into::<String>::into(msg_ref))
+    .try_map(|row: SqliteRow| {
+        Ok(MsgVote {
+            feed_ref: decode_feed_ref(row.get(0))?,
+            expression: row.get(1),
+            feed_seq: row.get::<i64, _>(2) as u64,
+        })
+    })
+    .fetch_all(connection)
+    .await
+}
+
+#[derive(Debug)]
+pub struct ExpressionCount {
+    // None for votes that didn't say how they were expressed
+    pub expression: Option<String>,
+    pub count: i64,
+}
+
+pub async fn select_vote_expression_counts(
+    connection: &mut SqliteConnection,
+    msg_ref: &MsgRef,
+) -> Result<Vec<ExpressionCount>, Error> {
+    query(
+        "
+        SELECT
+          votes.expression,
+          COUNT(*) AS count
+        FROM votes
+        JOIN msg_refs ON msg_refs.id = votes.link_to_msg_ref_id
+        WHERE
+            msg_refs.msg_ref = ?
+            AND votes.value > 0
+        GROUP BY votes.expression
+        ORDER BY count DESC, votes.expression
+        ",
+    )
+    .bind(Into::<String>::into(msg_ref))
+    .map(|row: SqliteRow| ExpressionCount {
+        expression: row.get(0),
+        count: row.get(1),
+    })
+    .fetch_all(connection)
+    .await
+}
+
+pub struct VotedPost {
+    pub log_seq: Sequence,
+    pub vote_count: i64,
+}
+
+// A feed's posts with the most votes, newest first among equal counts
+pub async fn select_most_voted_posts_by_feed(
+    connection: &mut SqliteConnection,
+    feed_ref: &FeedRef,
+    limit: i64,
+) -> Result<Vec<VotedPost>, Error> {
+    query(
+        "
+        SELECT
+          msgs.log_seq,
+          COUNT(*) AS vote_count
+        FROM votes
+        JOIN msgs ON msgs.msg_ref_id = votes.link_to_msg_ref_id
+        JOIN feed_refs ON feed_refs.id = msgs.feed_ref_id
+        WHERE
+            feed_refs.feed_ref = ?
+            AND msgs.content_type = 'post'
+            AND votes.value > 0
+        GROUP BY msgs.log_seq
+        ORDER BY vote_count DESC, msgs.log_seq DESC
+        LIMIT ?
+        ",
+    )
+    .bind(Into::<String>::into(feed_ref))
+    .bind(limit)
+    .map(|row: SqliteRow| VotedPost {
+        log_seq: row.get::<i64, _>(0) as Sequence,
+        vote_count: row.get(1),
+    })
+    .fetch_all(connection)
+    .await
+}
+
 pub async fn create_votes_indices(connection: &mut SqliteConnection) -> Result<(), Error> {
     trace!("Creating votes indices");
     query(
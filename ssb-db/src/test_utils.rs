@@ -0,0 +1,163 @@
+// Databases on temporary files, for tests that index msgs from an offset log.
+
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use ed25519_dalek::{PublicKey, SecretKey};
+use flumedb::{FlumeLog, OffsetLog};
+use futures::future::BoxFuture;
+use private_box::Keypair;
+use serde_json::{from_value, json, Value};
+use sqlx::{query, sqlite::SqliteRow, Error as SqlError, Row, SqliteConnection};
+use ssb_msg::{keypair_feed_ref, to_log_entry, Msg, MsgBuilder, MsgContent};
+use ssb_ref::FeedRef;
+use std::fs::{create_dir_all, remove_dir_all, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::box2::{box2, RecpKey};
+use crate::{Database, IndexedMsg, Indexer};
+
+static DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// An empty directory for the files of one test
+pub(crate) fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ssb-db-test-{}-{}",
+        std::process::id(),
+        DIR_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub(crate) fn log_path(dir: &Path) -> PathBuf {
+    dir.join("log.offset")
+}
+
+pub(crate) fn sql_path(dir: &Path) -> PathBuf {
+    dir.join("db.sqlite3")
+}
+
+pub(crate) fn keypair(seed: u8) -> ssb_msg::Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    ssb_msg::Keypair { secret, public }
+}
+
+// The same keypair as the indexer takes it, to decrypt private msgs with
+pub(crate) fn private_keypair(keypair: &ssb_msg::Keypair) -> Keypair {
+    Keypair::from_slice(&keypair.to_bytes()).unwrap()
+}
+
+pub(crate) fn post(text: &str) -> MsgContent {
+    from_value(json!({ "type": "post", "text": text })).unwrap()
+}
+
+pub(crate) async fn open_db(
+    dir: &Path,
+    keys: Vec<Keypair>,
+    indexers: Vec<Arc<dyn Indexer>>,
+) -> Database {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(dir))
+        .unwrap();
+    Database::new(log_path(dir), sql_path(dir), keys, None, indexers)
+        .await
+        .unwrap()
+}
+
+// Append a msg to the offset log without indexing it, as another writer would.
+pub(crate) fn append_to_log(dir: &Path, msg: &Msg<Value>) {
+    let entry = serde_json::to_vec(&to_log_entry(msg)).unwrap();
+    OffsetLog::<u32>::new(log_path(dir))
+        .unwrap()
+        .append(&entry)
+        .unwrap();
+}
+
+// Append the first msg of `author`, a box2 direct msg to `recp` with the content
+pub(crate) fn append_dm(
+    dir: &Path,
+    author: &ssb_msg::Keypair,
+    recp: &FeedRef,
+    content: Value,
+) -> Msg<Value> {
+    let mut msg = MsgBuilder::new(&post("")).unwrap().sign(author);
+    let dm_key = RecpKey::dm(author.secret.as_bytes(), author.public.as_bytes(), recp).unwrap();
+    let envelope = box2(
+        &serde_json::to_vec(&content).unwrap(),
+        &keypair_feed_ref(author),
+        None,
+        &[dm_key],
+    );
+    msg.value.content = Value::String(format!("{}.box2", b64.encode(envelope)));
+    append_to_log(dir, &msg);
+    msg
+}
+
+// A custom indexer keeping the text of every post in a table of its own
+pub(crate) struct PostTextIndexer;
+
+impl Indexer for PostTextIndexer {
+    fn name(&self) -> &str {
+        "post_texts"
+    }
+
+    fn create_tables<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> BoxFuture<'c, Result<(), SqlError>> {
+        Box::pin(async move {
+            query(
+                "
+                CREATE TABLE IF NOT EXISTS post_texts (
+                    msg_ref_id INTEGER PRIMARY KEY,
+                    text TEXT NOT NULL
+                )
+                ",
+            )
+            .execute(connection)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn index_msg<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+        msg: IndexedMsg<'c>,
+    ) -> BoxFuture<'c, Result<(), SqlError>> {
+        Box::pin(async move {
+            let MsgContent::Post(post) = msg.content else {
+                return Ok(());
+            };
+            query("INSERT OR REPLACE INTO post_texts (msg_ref_id, text) VALUES (?, ?)")
+                .bind(msg.msg_ref_id)
+                .bind(&post.text)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn clear<'c>(
+        &'c self,
+        connection: &'c mut SqliteConnection,
+    ) -> Option<BoxFuture<'c, Result<(), SqlError>>> {
+        Some(Box::pin(async move {
+            query("DELETE FROM post_texts").execute(connection).await?;
+            Ok(())
+        }))
+    }
+}
+
+pub(crate) async fn select_post_texts(connection: &mut SqliteConnection) -> Vec<String> {
+    query("SELECT text FROM post_texts ORDER BY msg_ref_id")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_all(connection)
+        .await
+        .unwrap()
+}
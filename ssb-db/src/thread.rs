@@ -0,0 +1,205 @@
+use serde_json::Value;
+use ssb_msg::Msg;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::sql::ThreadPost;
+
+#[derive(Debug)]
+pub struct Thread {
+    // None if the root msg is not in the log
+    pub root: Option<Msg<Value>>,
+    pub replies: Vec<ThreadNode>,
+    // Posts that start a new thread forked from this one
+    pub forks: Vec<Msg<Value>>,
+}
+
+#[derive(Debug)]
+pub struct ThreadNode {
+    pub msg: Msg<Value>,
+    pub replies: Vec<ThreadNode>,
+}
+
+// Build the reply tree of a thread.
+//
+// Each reply is placed under the latest (in causal order) of its branches that is in the
+// thread. Replies whose branches are all missing are placed directly under the root.
+pub(crate) fn build_thread_tree(posts: Vec<(ThreadPost, Msg<Value>)>) -> Vec<ThreadNode> {
+    let order = causal_order(
+        &posts
+            .iter()
+            .map(|(post, _)| post)
+            .collect::<Vec<&ThreadPost>>(),
+    );
+
+    let index_by_id: HashMap<i64, usize> = posts
+        .iter()
+        .enumerate()
+        .map(|(index, (post, _))| (post.msg_ref_id, index))
+        .collect();
+    let mut position = vec![0; posts.len()];
+    for (pos, index) in order.iter().enumerate() {
+        position[*index] = pos;
+    }
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); posts.len()];
+    let mut top_level = Vec::new();
+    for index in order.iter().copied() {
+        let parent = posts[index]
+            .0
+            .branch_msg_ref_ids
+            .iter()
+            .filter_map(|id| index_by_id.get(id).copied())
+            .filter(|parent| position[*parent] < position[index])
+            .max_by_key(|parent| position[*parent]);
+        match parent {
+            Some(parent) => children[parent].push(index),
+            None => top_level.push(index),
+        }
+    }
+
+    // children always come after their parent, so build the nodes from the back
+    let mut msgs: Vec<Option<Msg<Value>>> = posts.into_iter().map(|(_, msg)| Some(msg)).collect();
+    let mut nodes: Vec<Option<ThreadNode>> = (0..msgs.len()).map(|_| None).collect();
+    for index in order.iter().rev().copied() {
+        let replies = children[index]
+            .iter()
+            .filter_map(|child| nodes[*child].take())
+            .collect();
+        if let Some(msg) = msgs[index].take() {
+            nodes[index] = Some(ThreadNode { msg, replies });
+        }
+    }
+
+    top_level
+        .into_iter()
+        .filter_map(|index| nodes[index].take())
+        .collect()
+}
+
+// Order posts so that each post comes after the posts it links to, like ssb-sort.
+// Posts that don't link to each other are ordered by asserted timestamp.
+fn causal_order(posts: &[&ThreadPost]) -> Vec<usize> {
+    let index_by_id: HashMap<i64, usize> = posts
+        .iter()
+        .enumerate()
+        .map(|(index, post)| (post.msg_ref_id, index))
+        .collect();
+
+    let mut in_degrees = vec![0; posts.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); posts.len()];
+    for (index, post) in posts.iter().enumerate() {
+        for id in post.branch_msg_ref_ids.iter() {
+            if let Some(&dependency) = index_by_id.get(id) {
+                if dependency != index {
+                    in_degrees[index] += 1;
+                    dependents[dependency].push(index);
+                }
+            }
+        }
+    }
+
+    let sort_key = |index: usize| SortKey {
+        timestamp_asserted: posts[index].timestamp_asserted,
+        log_seq: posts[index].log_seq,
+        index,
+    };
+    let mut ready: BTreeSet<SortKey> = (0..posts.len())
+        .filter(|index| in_degrees[*index] == 0)
+        .map(sort_key)
+        .collect();
+
+    let mut order = Vec::with_capacity(posts.len());
+    let mut is_ordered = vec![false; posts.len()];
+    while let Some(key) = ready.pop_first() {
+        let index = key.index;
+        order.push(index);
+        is_ordered[index] = true;
+        for dependent in dependents[index].iter().copied() {
+            in_degrees[dependent] -= 1;
+            if in_degrees[dependent] == 0 {
+                ready.insert(sort_key(dependent));
+            }
+        }
+    }
+
+    // only a cycle of links can leave posts unordered, put them at the end
+    let mut rest: Vec<usize> = (0..posts.len())
+        .filter(|index| !is_ordered[*index])
+        .collect();
+    rest.sort_by_key(|index| sort_key(*index));
+    order.extend(rest);
+
+    order
+}
+
+// Posts are ordered by asserted timestamp, then by log seq. Timestamps are compared with
+// `f64::total_cmp`, so fractions of a millisecond still order posts.
+struct SortKey {
+    timestamp_asserted: f64,
+    log_seq: u64,
+    index: usize,
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp_asserted
+            .total_cmp(&other.timestamp_asserted)
+            .then(self.log_seq.cmp(&other.log_seq))
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(msg_ref_id: i64, timestamp_asserted: f64, branches: &[i64]) -> ThreadPost {
+        ThreadPost {
+            msg_ref_id,
+            log_seq: msg_ref_id as u64,
+            timestamp_asserted,
+            branch_msg_ref_ids: branches.to_vec(),
+        }
+    }
+
+    #[test]
+    fn causal_order_follows_links_before_timestamps() {
+        // 2 claims to be older than 1, but replies to it
+        let posts = [
+            post(1, 200.0, &[]),
+            post(2, 100.0, &[1]),
+            post(3, 150.0, &[]),
+        ];
+        let order = causal_order(&posts.iter().collect::<Vec<&ThreadPost>>());
+        assert_eq!(order, vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn causal_order_compares_fractional_timestamps() {
+        let posts = [post(1, 100.75, &[]), post(2, 100.25, &[])];
+        let order = causal_order(&posts.iter().collect::<Vec<&ThreadPost>>());
+        assert_eq!(order, vec![1, 0]);
+    }
+
+    #[test]
+    fn causal_order_ignores_missing_links() {
+        let posts = [post(1, 200.0, &[99]), post(2, 100.0, &[98])];
+        let order = causal_order(&posts.iter().collect::<Vec<&ThreadPost>>());
+        assert_eq!(order, vec![1, 0]);
+    }
+}
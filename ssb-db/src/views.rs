@@ -0,0 +1,161 @@
+use serde_json::{from_value, Value};
+use ssb_msg::{Msg, MsgContent, PostContent};
+use ssb_ref::{FeedRef, MsgRef};
+use std::collections::HashMap;
+
+use crate::profile::{build_profile, Profile};
+
+// Msgs with their typed content and what is needed to show them, built for a whole page of
+// msgs at once. Profiles in views leave out the name history of the feed.
+
+#[derive(Debug)]
+pub struct PostView {
+    pub msg: Msg<Value>,
+    pub content: PostContent,
+    pub author: Profile,
+    pub vote_count: i64,
+    // Posts with this post as their root
+    pub reply_count: i64,
+    pub root: Option<MsgRef>,
+    pub branch: Vec<MsgRef>,
+}
+
+#[derive(Debug)]
+pub struct ContactView {
+    pub msg: Msg<Value>,
+    pub author: Profile,
+    pub contact: Profile,
+    pub following: Option<bool>,
+    pub blocking: Option<bool>,
+}
+
+// Profiles keyed by feed ref, from the abouts of all the feeds
+pub(crate) fn build_profiles(
+    feed_refs: &[FeedRef],
+    abouts: Vec<(String, bool, Value)>,
+) -> HashMap<String, Profile> {
+    let mut abouts_by_subject: HashMap<String, Vec<(bool, Value)>> = HashMap::new();
+    for (subject, is_own, content) in abouts {
+        abouts_by_subject
+            .entry(subject)
+            .or_default()
+            .push((is_own, content));
+    }
+
+    feed_refs
+        .iter()
+        .map(|feed_ref| {
+            let key: String = feed_ref.into();
+            let abouts = abouts_by_subject.remove(&key).unwrap_or_default();
+            (key, build_profile(feed_ref, abouts, Vec::new()))
+        })
+        .collect()
+}
+
+// Msgs that aren't posts are left out.
+pub(crate) fn build_post_views(
+    msgs: Vec<Msg<Value>>,
+    profiles: &HashMap<String, Profile>,
+    vote_counts: &HashMap<String, i64>,
+    reply_counts: &HashMap<String, i64>,
+) -> Vec<PostView> {
+    msgs.into_iter()
+        .filter_map(|msg| {
+            let MsgContent::Post(content) = from_value(msg.value.content.clone()).ok()? else {
+                return None;
+            };
+            let key: String = (&msg.key).into();
+            Some(PostView {
+                author: profile(profiles, &msg.value.author),
+                vote_count: vote_counts.get(&key).copied().unwrap_or(0),
+                reply_count: reply_counts.get(&key).copied().unwrap_or(0),
+                root: content.root.clone(),
+                branch: content.branch.clone().unwrap_or_default(),
+                content,
+                msg,
+            })
+        })
+        .collect()
+}
+
+// Msgs that aren't contacts are left out.
+pub(crate) fn build_contact_views(
+    msgs: Vec<Msg<Value>>,
+    profiles: &HashMap<String, Profile>,
+) -> Vec<ContactView> {
+    msgs.into_iter()
+        .filter_map(|msg| {
+            let MsgContent::Contact(content) = from_value(msg.value.content.clone()).ok()? else {
+                return None;
+            };
+            Some(ContactView {
+                author: profile(profiles, &msg.value.author),
+                contact: profile(profiles, &content.contact),
+                following: content.following,
+                blocking: content.blocking,
+                msg,
+            })
+        })
+        .collect()
+}
+
+fn profile(profiles: &HashMap<String, Profile>, feed_ref: &FeedRef) -> Profile {
+    profiles
+        .get(&Into::<String>::into(feed_ref))
+        .cloned()
+        .unwrap_or_else(|| build_profile(feed_ref, Vec::new(), Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const AUTHOR: &str = "@J8jbLTPlPaKOsNzQ/nEDdJ+hHvFSrufJ7I9M2u6nlGs=.ed25519";
+    const ROOT: &str = "%Ao3O0vqSXpjkw3sl7dhfQSsY/B7Aa6BhBOdJOB0ufVQ=.sha256";
+    const REPLY: &str = "%fCxq7m1+9cWhBm0eFpWcLfD1dFZIBwUDD6vZhLtMpSk=.sha256";
+
+    fn msg(key: &str, content: Value) -> Msg<Value> {
+        from_value(json!({
+            "key": key,
+            "value": {
+                "previous": null,
+                "author": AUTHOR,
+                "sequence": 1,
+                "timestamp": 1000.0,
+                "content": content,
+                "signature": "",
+            },
+            "timestamp": 1000.0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn build_post_views_counts_votes_and_replies() {
+        let msgs = vec![
+            msg(ROOT, json!({ "type": "post", "text": "root" })),
+            msg(
+                REPLY,
+                json!({ "type": "post", "text": "reply", "root": ROOT, "branch": ROOT }),
+            ),
+            msg(
+                REPLY,
+                json!({ "type": "vote", "vote": { "link": ROOT, "value": 1 } }),
+            ),
+        ];
+        let author = FeedRef::from_string(AUTHOR.to_string()).unwrap();
+        let abouts = vec![(AUTHOR.to_string(), true, json!({ "name": "me" }))];
+        let profiles = build_profiles(&[author], abouts);
+        let vote_counts = HashMap::from([(ROOT.to_string(), 2)]);
+        let reply_counts = HashMap::from([(ROOT.to_string(), 1)]);
+
+        let views = build_post_views(msgs, &profiles, &vote_counts, &reply_counts);
+        assert_eq!(views.len(), 2);
+        assert_eq!(views[0].author.name.as_deref(), Some("me"));
+        assert_eq!((views[0].vote_count, views[0].reply_count), (2, 1));
+        assert_eq!((views[1].vote_count, views[1].reply_count), (0, 0));
+        assert_eq!(views[1].branch.len(), 1);
+        assert!(views[1].root.is_some());
+    }
+}
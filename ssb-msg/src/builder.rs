@@ -0,0 +1,211 @@
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use ed25519_dalek::{Keypair, Signer};
+use serde_json::Value;
+use ssb_ref::{FeedRef, MsgRef};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error as ThisError;
+
+use crate::legacy::{legacy_msg_ref, legacy_signature, to_legacy_json, to_legacy_value};
+use crate::{Msg, MsgContent, MsgValue};
+
+#[derive(Debug, ThisError)]
+pub enum BuildError {
+    #[error("Can't publish content of an unknown type")]
+    UnknownContent,
+    #[error("Json error, cause: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// Build and sign a new msg in the legacy format.
+//
+// Without `previous` the msg is the first of its feed.
+pub struct MsgBuilder {
+    content: Value,
+    previous: Option<MsgRef>,
+    sequence: u64,
+    timestamp: Option<f64>,
+}
+
+impl MsgBuilder {
+    pub fn new(content: &MsgContent) -> Result<Self, BuildError> {
+        if let MsgContent::Unknown = content {
+            return Err(BuildError::UnknownContent);
+        }
+        let mut content = serde_json::to_value(content)?;
+        remove_nulls(&mut content);
+
+        Ok(Self {
+            content,
+            previous: None,
+            sequence: 1,
+            timestamp: None,
+        })
+    }
+
+    // The key and sequence of the latest msg of the feed
+    pub fn previous(mut self, previous: MsgRef, sequence: u64) -> Self {
+        self.previous = Some(previous);
+        self.sequence = sequence + 1;
+        self
+    }
+
+    // Milliseconds since the unix epoch, defaults to now
+    pub fn timestamp(mut self, timestamp: f64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn sign(self, keypair: &Keypair) -> Msg<Value> {
+        let timestamp = self.timestamp.unwrap_or_else(now);
+        let author = keypair_feed_ref(keypair);
+
+        let mut value = MsgValue {
+            previous: self.previous,
+            author,
+            sequence: self.sequence,
+            timestamp_asserted: timestamp,
+            hash: MsgValue::<Value>::default_hash(),
+            content: self.content,
+            signature: None,
+            is_private: false,
+        };
+        let signed_json = to_legacy_json(&to_legacy_value(&value, true, false));
+        value.signature = Some(legacy_signature(
+            &keypair.sign(signed_json.as_bytes()).to_bytes(),
+        ));
+        let key = legacy_msg_ref(&to_legacy_json(&to_legacy_value(&value, true, true)));
+
+        Msg {
+            key,
+            value,
+            timestamp_received: timestamp,
+        }
+    }
+}
+
+// Optional fields are serialized as null, but are left out of published msgs.
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(values) => values.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+// The feed a keypair publishes to
+pub fn keypair_feed_ref(keypair: &Keypair) -> FeedRef {
+    FeedRef::from_string(format!(
+        "@{}.ed25519",
+        b64.encode(keypair.public.as_bytes())
+    ))
+    .unwrap()
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as f64)
+        .unwrap_or(0.0)
+}
+
+// The entry stored in the offset log for a msg, as other ssb implementations write it.
+pub fn to_log_entry(msg: &Msg<Value>) -> Value {
+    serde_json::json!({
+        "key": Into::<String>::into(&msg.key),
+        "value": to_legacy_value(&msg.value, true, true),
+        "timestamp": msg.timestamp_received,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PostContent;
+
+    fn keypair() -> Keypair {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn post(text: &str) -> MsgContent {
+        MsgContent::Post(PostContent {
+            text: text.to_string(),
+            channel: None,
+            mentions: None,
+            root: None,
+            branch: None,
+            fork: None,
+        })
+    }
+
+    #[test]
+    fn sign_builds_verifiable_chain() {
+        let keypair = keypair();
+        let first = MsgBuilder::new(&post("first"))
+            .unwrap()
+            .timestamp(1680000000000.0)
+            .sign(&keypair);
+        first.verify().unwrap();
+        assert_eq!(first.value.sequence, 1);
+        assert_eq!(
+            first.value.content,
+            serde_json::json!({ "type": "post", "text": "first" })
+        );
+
+        let second = MsgBuilder::new(&post("second"))
+            .unwrap()
+            .previous(first.key.clone(), first.value.sequence)
+            .sign(&keypair);
+        second.verify().unwrap();
+        assert_eq!(second.value.sequence, 2);
+        assert_eq!(
+            second.value.previous.map(|previous| previous.to_string()),
+            Some(first.key.to_string())
+        );
+    }
+
+    #[test]
+    fn log_entry_roundtrips() {
+        let msg = MsgBuilder::new(&post("hi")).unwrap().sign(&keypair());
+        let entry = serde_json::to_string(&to_log_entry(&msg)).unwrap();
+        let read: Msg<Value> = serde_json::from_str(&entry).unwrap();
+        read.verify().unwrap();
+    }
+
+    #[test]
+    fn refs_are_signed_as_strings() {
+        let keypair = keypair();
+        let root = MsgBuilder::new(&post("root")).unwrap().sign(&keypair);
+        let reply = MsgBuilder::new(&MsgContent::Post(PostContent {
+            root: Some(root.key.clone()),
+            branch: Some(vec![root.key.clone()]),
+            ..match post("reply") {
+                MsgContent::Post(post) => post,
+                _ => unreachable!(),
+            }
+        }))
+        .unwrap()
+        .previous(root.key.clone(), root.value.sequence)
+        .sign(&keypair);
+
+        let entry = serde_json::to_value(to_log_entry(&reply)).unwrap();
+        let root_id = root.key.to_string();
+        assert_eq!(entry["value"]["previous"], root_id);
+        assert_eq!(
+            entry["value"]["author"],
+            crate::keypair_feed_ref(&keypair).to_string()
+        );
+        assert_eq!(entry["value"]["content"]["root"], root_id);
+        // a single branch is written as one ref rather than a list
+        assert_eq!(entry["value"]["content"]["branch"], root_id);
+    }
+
+    #[test]
+    fn new_rejects_unknown_content() {
+        assert!(MsgBuilder::new(&MsgContent::Unknown).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+// The legacy message format: the key of a msg is the sha256 of its value encoded as
+// `JSON.stringify(value, null, 2)`, and the signature is over the same encoding without
+// the signature field.
+//
+// https://ssbc.github.io/scuttlebutt-protocol-guide/#message-format
+
+use base64::engine::{general_purpose::STANDARD as b64, Engine};
+use ed25519_dalek::{PublicKey, Signature, SignatureError, Verifier};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use ssb_ref::{FeedRef, MsgRef};
+use thiserror::Error as ThisError;
+
+use crate::{Msg, MsgValue};
+
+#[derive(Debug, ThisError)]
+pub enum VerifyError {
+    #[error("Unsupported hash function: {0}")]
+    UnsupportedHash(String),
+    #[error("Msg key {actual} does not match the hash of its value {expected}")]
+    KeyMismatch { expected: String, actual: String },
+    #[error("Msg has no signature")]
+    MissingSignature,
+    #[error("Signature is not a base64 encoded ed25519 signature: {0}")]
+    BadSignatureFormat(String),
+    #[error("Signature does not match author {author}")]
+    InvalidSignature {
+        author: String,
+        #[source]
+        source: SignatureError,
+    },
+}
+
+impl Msg<Value> {
+    // Check that the key is the hash of the value and that the author signed it.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let value = &self.value;
+        if value.hash != "sha256" {
+            return Err(VerifyError::UnsupportedHash(value.hash.clone()));
+        }
+        let signature = value
+            .signature
+            .as_deref()
+            .ok_or(VerifyError::MissingSignature)?;
+
+        // early msgs put `sequence` before `author`, so accept either order
+        let key = self.key.to_string();
+        let hash_value = |is_author_first| {
+            legacy_msg_ref(&to_legacy_json(&to_legacy_value(
+                value,
+                is_author_first,
+                true,
+            )))
+            .to_string()
+        };
+        let is_author_first = [true, false]
+            .into_iter()
+            .find(|is_author_first| hash_value(*is_author_first) == key)
+            .ok_or_else(|| VerifyError::KeyMismatch {
+                expected: hash_value(true),
+                actual: key.clone(),
+            })?;
+
+        let signed_json = to_legacy_json(&to_legacy_value(value, is_author_first, false));
+        verify_signature(&value.author, signature, signed_json.as_bytes())
+    }
+}
+
+// Build the value in the order its fields are encoded in
+pub fn to_legacy_value(
+    value: &MsgValue<Value>,
+    is_author_first: bool,
+    with_signature: bool,
+) -> Value {
+    let mut map = Map::new();
+    map.insert(
+        "previous".to_string(),
+        value
+            .previous
+            .as_ref()
+            .map_or(Value::Null, |previous| Value::String(previous.into())),
+    );
+    let author = Value::String((&value.author).into());
+    let sequence = Value::from(value.sequence);
+    if is_author_first {
+        map.insert("author".to_string(), author);
+        map.insert("sequence".to_string(), sequence);
+    } else {
+        map.insert("sequence".to_string(), sequence);
+        map.insert("author".to_string(), author);
+    }
+    map.insert(
+        "timestamp".to_string(),
+        Value::from(value.timestamp_asserted),
+    );
+    map.insert("hash".to_string(), Value::String(value.hash.clone()));
+    map.insert("content".to_string(), value.content.clone());
+    if let (true, Some(signature)) = (with_signature, &value.signature) {
+        map.insert("signature".to_string(), Value::String(signature.clone()));
+    }
+    Value::Object(map)
+}
+
+// The msg key is hashed from the utf-16 code units of the json truncated to bytes, as
+// node's "binary" encoding does.
+pub fn legacy_msg_ref(json: &str) -> MsgRef {
+    let bytes: Vec<u8> = json.encode_utf16().map(|unit| unit as u8).collect();
+    let hash = Sha256::digest(bytes);
+    MsgRef::from_string(format!("%{}.sha256", b64.encode(hash))).unwrap()
+}
+
+pub fn legacy_signature(signature: &[u8]) -> String {
+    format!("{}.sig.ed25519", b64.encode(signature))
+}
+
+fn verify_signature(author: &FeedRef, signature: &str, signed: &[u8]) -> Result<(), VerifyError> {
+    let bad_format = || VerifyError::BadSignatureFormat(signature.to_string());
+    let signature_bytes = signature
+        .strip_suffix(".sig.ed25519")
+        .and_then(|data| b64.decode(data).ok())
+        .ok_or_else(bad_format)?;
+    let signature = Signature::try_from(signature_bytes.as_slice()).map_err(|_| bad_format())?;
+
+    let invalid = |source| VerifyError::InvalidSignature {
+        author: author.to_string(),
+        source,
+    };
+    let public_key = PublicKey::from_bytes(author.as_bytes()).map_err(invalid)?;
+    public_key.verify(signed, &signature).map_err(invalid)
+}
+
+// Encode like `JSON.stringify(value, null, 2)`.
+pub fn to_legacy_json(value: &Value) -> String {
+    let mut json = String::new();
+    write_value(&mut json, value, 0);
+    json
+}
+
+fn write_value(json: &mut String, value: &Value, depth: usize) {
+    match value {
+        Value::Null => json.push_str("null"),
+        Value::Bool(boolean) => json.push_str(if *boolean { "true" } else { "false" }),
+        Value::Number(number) => {
+            if let Some(integer) = number.as_i64() {
+                json.push_str(&integer.to_string())
+            } else if let Some(integer) = number.as_u64() {
+                json.push_str(&integer.to_string())
+            } else {
+                json.push_str(&to_js_number(number.as_f64().unwrap_or(0.0)))
+            }
+        }
+        Value::String(string) => write_string(json, string),
+        Value::Array(values) => {
+            if values.is_empty() {
+                json.push_str("[]");
+                return;
+            }
+            json.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_newline(json, depth + 1);
+                write_value(json, value, depth + 1);
+            }
+            write_newline(json, depth);
+            json.push(']');
+        }
+        Value::Object(map) => {
+            if map.is_empty() {
+                json.push_str("{}");
+                return;
+            }
+            json.push('{');
+            for (index, (key, value)) in map.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_newline(json, depth + 1);
+                write_string(json, key);
+                json.push_str(": ");
+                write_value(json, value, depth + 1);
+            }
+            write_newline(json, depth);
+            json.push('}');
+        }
+    }
+}
+
+fn write_newline(json: &mut String, depth: usize) {
+    json.push('\n');
+    for _ in 0..depth {
+        json.push_str("  ");
+    }
+}
+
+// serde_json escapes the same characters as JSON.stringify
+fn write_string(json: &mut String, string: &str) {
+    json.push_str(&serde_json::to_string(string).unwrap());
+}
+
+// Format a float like javascript's Number.prototype.toString.
+fn to_js_number(number: f64) -> String {
+    if !number.is_finite() {
+        return "null".to_string();
+    }
+    if number == 0.0 {
+        return "0".to_string();
+    }
+    if number < 0.0 {
+        return format!("-{}", to_js_number(-number));
+    }
+
+    // the shortest digits that round trip, and the exponent of the first digit
+    let scientific = format!("{:e}", number);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap() + 1;
+
+    if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n - 1 < 0 { '-' } else { '+' };
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{}{}{}e{}{}", first, point, rest, sign, (n - 1).abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSG: &str = r#"{"key": "%pA5u809YjNUMvwF45xfXlKhWRBoQrsiYp31+jN+wfHY=.sha256", "value": {"previous": null, "author": "@A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=.ed25519", "sequence": 1, "timestamp": 1680000000000, "hash": "sha256", "content": {"type": "post", "text": "héllo ✓ 😀", "mentions": []}, "signature": "7IXfz2w5P01LwRoc/iGmsGcAA9ZU7GU/McGenhdO3htvpeQBsjwyT//9rAGaHVT+yzlndbthiNRjxbo7ZnHBDQ==.sig.ed25519"}, "timestamp": 1680000000123.5}"#;
+
+    #[test]
+    fn verify_signed_msg() {
+        let msg: Msg<Value> = serde_json::from_str(MSG).unwrap();
+        msg.verify().unwrap();
+    }
+
+    #[test]
+    fn verify_rejects_tampered_content() {
+        let mut msg: Msg<Value> = serde_json::from_str(MSG).unwrap();
+        msg.value.content["text"] = Value::String("hello".to_string());
+        assert!(matches!(msg.verify(), Err(VerifyError::KeyMismatch { .. })));
+    }
+
+    #[test]
+    fn verify_rejects_msg_without_signature() {
+        let mut msg: Value = serde_json::from_str(MSG).unwrap();
+        msg["value"].as_object_mut().unwrap().remove("signature");
+        let msg: Msg<Value> = serde_json::from_value(msg).unwrap();
+        assert!(matches!(msg.verify(), Err(VerifyError::MissingSignature)));
+    }
+
+    #[test]
+    fn to_js_number_matches_javascript() {
+        assert_eq!(to_js_number(1680000000000.0), "1680000000000");
+        assert_eq!(to_js_number(1.5), "1.5");
+        assert_eq!(to_js_number(0.000001), "0.000001");
+        assert_eq!(to_js_number(0.0000001), "1e-7");
+        assert_eq!(to_js_number(1.5e21), "1.5e+21");
+        assert_eq!(to_js_number(-2.25), "-2.25");
+    }
+}
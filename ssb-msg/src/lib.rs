@@ -7,7 +7,12 @@ use serde::{
 use serde_with::{serde_as, DefaultOnError, OneOrMany};
 use ssb_ref::{BlobRef, FeedRef, HashtagRef, LinkRef, MsgRef, RefError};
 use std::{fmt, str::FromStr};
-// use thiserror::Error as ThisError;
+
+mod builder;
+mod legacy;
+pub use builder::{keypair_feed_ref, to_log_entry, BuildError, MsgBuilder};
+pub use ed25519_dalek::Keypair;
+pub use legacy::{legacy_msg_ref, legacy_signature, to_legacy_json, to_legacy_value, VerifyError};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Msg<Content> {
@@ -19,34 +24,51 @@ pub struct Msg<Content> {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MsgValue<Content> {
-    // pub previous: MsgRef,
+    // None for the first msg of a feed
+    pub previous: Option<MsgRef>,
     pub author: FeedRef,
     pub sequence: u64,
     #[serde(alias = "timestamp")]
     pub timestamp_asserted: f64,
-    // #[serde(default = "MsgValue::<Content>::default_hash")]
-    // pub hash: String,
+    #[serde(default = "MsgValue::<Content>::default_hash")]
+    pub hash: String,
     pub content: Content,
-    // pub signature: String,
+    // None for entries written without one, so they can still be read and then fail to verify
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Set when the content is encrypted in the log, it is the decrypted content if a key
+    // could decrypt it
+    #[serde(
+        default,
+        rename = "private",
+        skip_serializing_if = "MsgValue::<Content>::is_public"
+    )]
+    pub is_private: bool,
 }
 
 impl<Content> MsgValue<Content> {
     pub fn default_hash() -> String {
         "sha256".to_string()
     }
+
+    fn is_public(is_private: &bool) -> bool {
+        !is_private
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum MsgContent {
-    #[serde(alias = "post")]
+    #[serde(alias = "post", rename(serialize = "post"))]
     Post(PostContent),
-    #[serde(alias = "contact")]
+    #[serde(alias = "contact", rename(serialize = "contact"))]
     Contact(ContactContent),
-    #[serde(alias = "vote")]
+    #[serde(alias = "vote", rename(serialize = "vote"))]
     Vote(VoteContent),
-    #[serde(alias = "about")]
+    #[serde(alias = "about", rename(serialize = "about"))]
     About(AboutContent),
+    #[serde(alias = "group/add-member", rename(serialize = "group/add-member"))]
+    GroupAddMember(GroupAddMemberContent),
     /*
     Blog(BlogContent),
     Alias(AliasContent),
@@ -97,7 +119,7 @@ pub struct BlobLink {
     #[serde(default)]
     pub size: Option<u64>,
     #[serde_as(deserialize_as = "DefaultOnError")]
-    #[serde(alias = "type")]
+    #[serde(alias = "type", rename(serialize = "type"))]
     #[serde(default)]
     pub mime_type: Option<String>,
 }
@@ -165,6 +187,21 @@ pub struct AboutContent {
     pub image: Option<BlobLink>,
 }
 
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupAddMemberContent {
+    // The base64 encoded symmetric key of the group
+    pub group_key: String,
+    // The msg that created the group
+    pub root: MsgRef,
+    // The cloaked id of the group followed by the feeds being added
+    pub recps: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
 /*
 pub struct BlogContent {
     title: String,
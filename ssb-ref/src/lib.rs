@@ -24,7 +24,7 @@ pub enum RefError {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String")]
+#[serde(try_from = "String", into = "String")]
 pub struct FeedRef(Vec<u8>);
 
 impl FeedRef {
@@ -40,10 +40,20 @@ impl FeedRef {
         }
     }
 
+    // From the url-safe data used in page urls
+    pub fn from_urlsafe_data(data: &str) -> Result<Self, RefError> {
+        Self::from_string(format!("@{}.ed25519", b64.encode(b64url.decode(data)?)))
+    }
+
     pub fn to_string(&self) -> String {
         format!("@{}.ed25519", self.string_data())
     }
 
+    // The raw ed25519 public key
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     pub fn single_regex() -> &'static Regex {
         lazy_static! {
             static ref RE: Regex = canonical_base64("@", ".ed25519", 32, true);
@@ -89,6 +99,12 @@ impl TryFrom<String> for FeedRef {
     }
 }
 
+impl From<FeedRef> for String {
+    fn from(value: FeedRef) -> String {
+        value.to_string()
+    }
+}
+
 impl From<&FeedRef> for String {
     fn from(value: &FeedRef) -> String {
         value.to_string()
@@ -96,7 +112,7 @@ impl From<&FeedRef> for String {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String")]
+#[serde(try_from = "String", into = "String")]
 pub struct MsgRef(Vec<u8>);
 
 impl MsgRef {
@@ -112,10 +128,20 @@ impl MsgRef {
         }
     }
 
+    // From the url-safe data used in page urls
+    pub fn from_urlsafe_data(data: &str) -> Result<Self, RefError> {
+        Self::from_string(format!("%{}.sha256", b64.encode(b64url.decode(data)?)))
+    }
+
     pub fn to_string(&self) -> String {
         format!("%{}.sha256", self.string_data())
     }
 
+    // The raw sha256 hash
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     pub fn single_regex() -> &'static Regex {
         lazy_static! {
             static ref RE: Regex = canonical_base64("%", ".sha256", 32, true);
@@ -161,6 +187,12 @@ impl TryFrom<String> for MsgRef {
     }
 }
 
+impl From<MsgRef> for String {
+    fn from(value: MsgRef) -> String {
+        value.to_string()
+    }
+}
+
 impl From<&MsgRef> for String {
     fn from(value: &MsgRef) -> String {
         value.to_string()
@@ -168,7 +200,7 @@ impl From<&MsgRef> for String {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String")]
+#[serde(try_from = "String", into = "String")]
 pub struct BlobRef(Vec<u8>);
 
 impl BlobRef {
@@ -233,6 +265,12 @@ impl TryFrom<String> for BlobRef {
     }
 }
 
+impl From<BlobRef> for String {
+    fn from(value: BlobRef) -> String {
+        value.to_string()
+    }
+}
+
 impl From<&BlobRef> for String {
     fn from(value: &BlobRef) -> String {
         value.to_string()
@@ -240,7 +278,7 @@ impl From<&BlobRef> for String {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String")]
+#[serde(try_from = "String", into = "String")]
 pub struct HashtagRef(String);
 
 impl HashtagRef {
@@ -289,7 +327,8 @@ impl HashtagRef {
         format!("/hashtag/{}", urlsafe_tag)
     }
 
-    fn parse_tag(&self) -> String {
+    // The tag without its leading #
+    pub fn parse_tag(&self) -> String {
         let regex = Self::single_regex();
         let caps = regex.captures(self.0.as_str()).unwrap();
         caps.name("tag").unwrap().as_str().to_string()
@@ -304,6 +343,12 @@ impl TryFrom<String> for HashtagRef {
     }
 }
 
+impl From<HashtagRef> for String {
+    fn from(value: HashtagRef) -> String {
+        value.to_string()
+    }
+}
+
 impl From<&HashtagRef> for String {
     fn from(value: &HashtagRef) -> String {
         value.to_string()
@@ -311,7 +356,7 @@ impl From<&HashtagRef> for String {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String")]
+#[serde(try_from = "String", into = "String")]
 pub enum LinkRef {
     Feed(FeedRef),
     Msg(MsgRef),
@@ -394,6 +439,12 @@ impl TryFrom<String> for LinkRef {
     }
 }
 
+impl From<LinkRef> for String {
+    fn from(value: LinkRef) -> String {
+        value.to_string()
+    }
+}
+
 impl From<&LinkRef> for String {
     fn from(value: &LinkRef) -> String {
         value.to_string()
@@ -487,6 +538,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_feed_id_from_urlsafe_data() {
+        let feed_ref = FeedRef::from_string(
+            "@jEA8WSl0URsB/g/XYG5zCGBkMOyTeBZfGtbw3RJMIuk=.ed25519".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            FeedRef::from_urlsafe_data(&feed_ref.urlsafe_data())
+                .unwrap()
+                .to_string(),
+            feed_ref.to_string()
+        );
+    }
+
+    #[test]
+    fn test_message_id_from_urlsafe_data() {
+        let msg_ref =
+            MsgRef::from_string("%09abcdefghyq9KH6dYMc/g17L04jDbl1py8arGQmL1I=.sha256".to_string())
+                .unwrap();
+        assert_eq!(
+            MsgRef::from_urlsafe_data(&msg_ref.urlsafe_data())
+                .unwrap()
+                .to_string(),
+            msg_ref.to_string()
+        );
+    }
+
     #[test]
     fn test_is_blob_id() {
         assert!(BlobRef::is_match(